@@ -0,0 +1,89 @@
+//! Compares drawing every wallpaper from scratch with redrawing only what changed since the
+//! last battery level, run it with `cargo bench`
+
+// ruin is a binary, so its modules are pulled in directly and most of them go unused here
+#![allow(dead_code)]
+
+#[path = "../src/animation.rs"]
+mod animation;
+#[path = "../src/backend.rs"]
+mod backend;
+#[path = "../src/battery.rs"]
+mod battery;
+#[path = "../src/color.rs"]
+mod color;
+#[path = "../src/colorscheme.rs"]
+mod colorscheme;
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/render.rs"]
+mod render;
+#[path = "../src/text.rs"]
+mod text;
+
+use battery::{Battery, BatteryStatus};
+use colorscheme::Colors;
+use config::Config;
+use image::{Rgba, RgbaImage};
+use render::{create, Canvas, Indicator};
+use std::{
+    env, fs,
+    time::{Duration, Instant},
+};
+
+const WIDTH: u32 = 3840;
+const HEIGHT: u32 = 2160;
+
+fn main() {
+    // A battery shaped image like the ones in the images directory
+    let ruin_dir = env::temp_dir().join(format!("ruin-bench-{}", std::process::id()));
+    fs::create_dir_all(ruin_dir.join("images")).unwrap();
+    RgbaImage::from_fn(400, 800, |x, y| match (x, y) {
+        (70..=329, 90..=749) => Rgba([143, 188, 187, 255]),
+        (40..=359, 60..=779) | (140..=259, 20..=59) => Rgba([220, 220, 220, 255]),
+        _ => Rgba([0, 0, 0, 0]),
+    })
+    .save(ruin_dir.join("images/bench.png"))
+    .unwrap();
+
+    let config = Config::default();
+    let colors = Colors::default();
+    let indicator = Indicator::load(&ruin_dir, "bench", &config).unwrap();
+    fs::remove_dir_all(&ruin_dir).unwrap();
+
+    // Draining from full to empty in small steps, like a fractional level does
+    let levels = (0..=400)
+        .rev()
+        .map(|level| {
+            vec![Battery {
+                status: BatteryStatus::Discharging,
+                capacity: level as f32 / 4.0,
+                energy: None,
+                rate: None,
+                time_to_empty: None,
+                time_to_full: None,
+                health: None,
+                cycle_count: None,
+            }]
+        })
+        .collect::<Vec<_>>();
+
+    let full = time(&levels, |batteries| {
+        create(batteries, &colors, &config, &indicator, WIDTH, HEIGHT);
+    });
+
+    let mut canvas = Canvas::new(&indicator, &colors, 1, WIDTH, HEIGHT);
+    let incremental = time(&levels, |batteries| {
+        canvas.update(batteries, &colors, &config, &indicator);
+    });
+
+    println!("{} redraws at {WIDTH}x{HEIGHT}", levels.len());
+    println!("from scratch: {:?} per redraw", full);
+    println!("incremental:  {:?} per redraw", incremental);
+}
+
+fn time(levels: &[Vec<Battery>], mut redraw: impl FnMut(&[Battery])) -> Duration {
+    let start = Instant::now();
+    levels.iter().for_each(|batteries| redraw(batteries));
+    start.elapsed() / levels.len() as u32
+}
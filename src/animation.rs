@@ -0,0 +1,98 @@
+use crate::{
+    backend::Output,
+    render::{blend, Filled},
+};
+use image::RgbaImage;
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+/// Moving effect drawn over the fill while charging
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Animation {
+    pub effect: Option<Effect>,
+    /// Frames per second
+    pub fps: u32,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            effect: None,
+            fps: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    /// The whole fill slowly brightens and dims
+    Pulse,
+    /// A soft band of light rises through the fill
+    Wave,
+    /// A narrow glint sweeps through the fill, then rests for a moment
+    Shimmer,
+}
+
+impl Effect {
+    /// How much a pixel this far along the fill is lit up at `time` seconds, from 0 to 1
+    fn highlight(self, progress: f32, time: f32) -> f32 {
+        match self {
+            Self::Pulse => 0.35 * (0.5 - 0.5 * (time * PI).cos()),
+            Self::Wave => {
+                let center = (time / 2.5).fract() * 1.4 - 0.2;
+                0.4 * (1.0 - (progress - center).abs() / 0.2).max(0.0)
+            }
+            Self::Shimmer => {
+                let center = (time / 2.0).fract() / 0.3 * 1.2 - 0.1;
+                0.7 * (1.0 - (progress - center).abs() / 0.04).max(0.0)
+            }
+        }
+    }
+}
+
+/// Rendered wallpapers the animation frames are drawn into, only the filled pixels are touched
+/// for each frame
+pub struct Animated {
+    effect: Effect,
+    frames: Vec<(Output, RgbaImage)>,
+    /// Filled pixels of each wallpaper along with their color without any highlight
+    fills: Vec<Vec<(Filled, [u8; 4])>>,
+}
+
+impl Animated {
+    pub fn new(effect: Effect, wallpapers: Vec<(Output, RgbaImage, Vec<Filled>)>) -> Self {
+        let (frames, fills) = wallpapers
+            .into_iter()
+            .map(|(output, wallpaper, fill)| {
+                let fill = fill
+                    .into_iter()
+                    .map(|filled| (filled, wallpaper.get_pixel(filled.0, filled.1).0))
+                    .collect();
+                ((output, wallpaper), fill)
+            })
+            .unzip();
+
+        Self {
+            effect,
+            frames,
+            fills,
+        }
+    }
+
+    /// Draws the frame `time` seconds into the animation
+    pub fn frame(&mut self, time: f32) -> &[(Output, RgbaImage)] {
+        self.frames
+            .iter_mut()
+            .zip(&self.fills)
+            .for_each(|((_, frame), fill)| {
+                fill.iter().for_each(|&((x, y, weight, progress), pixel)| {
+                    let highlight = self.effect.highlight(progress, time) * weight;
+                    frame.put_pixel(x, y, blend(pixel, [255, 255, 255, 255], highlight));
+                });
+            });
+
+        &self.frames
+    }
+}
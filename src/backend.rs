@@ -0,0 +1,465 @@
+use clap::ValueEnum;
+use image::{imageops, DynamicImage, RgbaImage};
+use std::{
+    env,
+    error::Error,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    process::{self, Child},
+};
+use wayland_client::{
+    protocol::{wl_output, wl_registry},
+    Connection as WaylandConnection, Dispatch, QueueHandle, WEnum,
+};
+use wlrs::{CropMode, Image, SetType};
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{
+        randr::ConnectionExt as _,
+        xproto::{
+            AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateGCAux, ImageFormat,
+            ImageOrder, PropMode, Screen,
+        },
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum BackendKind {
+    Wlrs,
+    File,
+    Command,
+    X11,
+}
+
+/// A screen the wallpaper is shown on, a wallpaper is rendered for each one at its own size
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// Name of the output, None when the backend can't tell outputs apart
+    pub name: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            name: None,
+            x: 0,
+            y: 0,
+            width: 3840,
+            height: 2160,
+        }
+    }
+}
+
+pub trait Backend {
+    fn outputs(&mut self) -> Result<Vec<Output>, Box<dyn Error>> {
+        Ok(vec![Output::default()])
+    }
+
+    /// Sets the wallpapers, there is one for every output returned by [`Backend::outputs`]
+    fn set(&mut self, wallpapers: &[(Output, RgbaImage)]) -> Result<(), Box<dyn Error>>;
+
+    /// Whether the backend keeps up with animation frames
+    fn animates(&self) -> bool {
+        true
+    }
+
+    /// The file the wallpaper is written to, if there is one
+    fn file(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Sets the wallpaper on wayland compositors through wlrs
+pub struct Wlrs {
+    wlrs: wlrs::Wlrs,
+    outputs: Vec<String>,
+}
+
+impl Wlrs {
+    pub fn new(outputs: Vec<String>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            wlrs: wlrs::Wlrs::new()?,
+            outputs,
+        })
+    }
+}
+
+impl Backend for Wlrs {
+    fn outputs(&mut self) -> Result<Vec<Output>, Box<dyn Error>> {
+        let conn = WaylandConnection::connect_to_env()?;
+        let mut queue = conn.new_event_queue();
+        conn.display().get_registry(&queue.handle(), ());
+
+        // The first roundtrip announces the outputs, the second one their properties
+        let mut outputs = WaylandOutputs(Vec::new());
+        queue.roundtrip(&mut outputs)?;
+        queue.roundtrip(&mut outputs)?;
+
+        Ok(outputs
+            .0
+            .into_iter()
+            .map(
+                |WaylandOutput {
+                     mut output,
+                     rotated,
+                 }| {
+                    // Rotated outputs are taller than they are wide
+                    if rotated {
+                        (output.width, output.height) = (output.height, output.width);
+                    }
+                    output
+                },
+            )
+            .filter(|output| match &output.name {
+                Some(name) => self.outputs.is_empty() || self.outputs.contains(name),
+                None => true,
+            })
+            .collect())
+    }
+
+    fn set(&mut self, wallpapers: &[(Output, RgbaImage)]) -> Result<(), Box<dyn Error>> {
+        wallpapers.iter().try_for_each(|(output, image)| {
+            let image_data = Image::new(
+                image,
+                NonZeroU32::new(image.width()).ok_or("Image has no width")?,
+                NonZeroU32::new(image.height()).ok_or("Image has no height")?,
+            )?;
+            let outputs = match &output.name {
+                Some(name) => std::slice::from_ref(name),
+                None => &self.outputs,
+            };
+            self.wlrs
+                .set(SetType::Img(image_data), outputs, CropMode::Fit(None))
+        })
+    }
+}
+
+#[derive(Default)]
+struct WaylandOutput {
+    output: Output,
+    rotated: bool,
+}
+
+struct WaylandOutputs(Vec<WaylandOutput>);
+
+impl Dispatch<wl_registry::WlRegistry, ()> for WaylandOutputs {
+    fn event(
+        outputs: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &WaylandConnection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            if interface == "wl_output" {
+                // Output names were only added in version 4
+                registry.bind::<wl_output::WlOutput, _, _>(
+                    name,
+                    version.min(4),
+                    qh,
+                    outputs.0.len(),
+                );
+                outputs.0.push(WaylandOutput::default());
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, usize> for WaylandOutputs {
+    fn event(
+        outputs: &mut Self,
+        _: &wl_output::WlOutput,
+        event: wl_output::Event,
+        index: &usize,
+        _: &WaylandConnection,
+        _: &QueueHandle<Self>,
+    ) {
+        let WaylandOutput { output, rotated } = &mut outputs.0[*index];
+        match event {
+            wl_output::Event::Geometry {
+                x,
+                y,
+                transform: WEnum::Value(transform),
+                ..
+            } => {
+                (output.x, output.y) = (x, y);
+                *rotated = matches!(
+                    transform,
+                    wl_output::Transform::_90
+                        | wl_output::Transform::_270
+                        | wl_output::Transform::Flipped90
+                        | wl_output::Transform::Flipped270
+                );
+            }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(wl_output::Mode::Current) => {
+                (output.width, output.height) = (width as u32, height as u32);
+            }
+            wl_output::Event::Name { name } => output.name = Some(name),
+            _ => {}
+        }
+    }
+}
+
+/// Writes the wallpaper to a file, the format is picked from the extension
+pub struct File {
+    path: PathBuf,
+}
+
+impl File {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl Backend for File {
+    fn set(&mut self, wallpapers: &[(Output, RgbaImage)]) -> Result<(), Box<dyn Error>> {
+        let (_, image) = wallpapers.first().ok_or("No wallpaper to write")?;
+        // Not every format can store an alpha channel, it's only kept for see-through
+        // backgrounds and dropped by the formats that can't
+        let opaque = image.pixels().all(|pixel| pixel.0[3] == 255);
+        let image = DynamicImage::ImageRgba8(image.clone());
+        match opaque {
+            true => image.to_rgb8().save(&self.path)?,
+            false => image
+                .save(&self.path)
+                .or_else(|_| image.to_rgb8().save(&self.path))?,
+        }
+
+        Ok(())
+    }
+
+    /// Encoding a whole image for every frame takes far too long
+    fn animates(&self) -> bool {
+        false
+    }
+
+    fn file(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// Writes the wallpaper to a file and hands it over to an external program like swww or feh
+pub struct Command {
+    command: String,
+    file: File,
+    path: PathBuf,
+    child: Option<Child>,
+}
+
+impl Command {
+    /// `{file}` in `command` is replaced with the path of the rendered image, the path is
+    /// appended to the end if there is no placeholder
+    pub fn new(command: String) -> Self {
+        let path = dirs::runtime_dir()
+            .unwrap_or_else(env::temp_dir)
+            .join("ruin.png");
+        let command = match command.contains("{file}") {
+            true => command.replace("{file}", "\"$1\""),
+            false => format!("{command} \"$1\""),
+        };
+
+        Self {
+            command,
+            file: File::new(path.clone()),
+            path,
+            child: None,
+        }
+    }
+}
+
+impl Backend for Command {
+    fn set(&mut self, wallpapers: &[(Output, RgbaImage)]) -> Result<(), Box<dyn Error>> {
+        self.file.set(wallpapers)?;
+
+        let child = process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .arg("ruin")
+            .arg(&self.path)
+            .spawn()?;
+
+        // Long running programs like swaybg would pile up otherwise, the previous one is only
+        // stopped once its replacement is started to avoid flickering
+        if let Some(mut previous) = self.child.replace(child) {
+            _ = previous.kill();
+            _ = previous.wait();
+        }
+
+        Ok(())
+    }
+
+    /// On top of writing the file, the program would be restarted for every frame
+    fn animates(&self) -> bool {
+        false
+    }
+
+    fn file(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// Sets the background of the X11 root window
+pub struct X11 {
+    conn: RustConnection,
+    screen: usize,
+    pixmap: Option<u32>,
+}
+
+impl X11 {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let (conn, screen) = x11rb::connect(None)?;
+        Ok(Self {
+            conn,
+            screen,
+            pixmap: None,
+        })
+    }
+
+    fn screen(&self) -> &Screen {
+        &self.conn.setup().roots[self.screen]
+    }
+}
+
+impl Backend for X11 {
+    fn outputs(&mut self) -> Result<Vec<Output>, Box<dyn Error>> {
+        let screen = self.screen();
+        let whole = Output {
+            width: screen.width_in_pixels as u32,
+            height: screen.height_in_pixels as u32,
+            ..Default::default()
+        };
+
+        // Without RandR the whole screen is treated as one output
+        let Ok(monitors) = self
+            .conn
+            .randr_get_monitors(screen.root, true)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|cookie| Ok(cookie.reply()?.monitors))
+        else {
+            return Ok(vec![whole]);
+        };
+        if monitors.is_empty() {
+            return Ok(vec![whole]);
+        }
+
+        monitors
+            .into_iter()
+            .map(|monitor| {
+                let name = self.conn.get_atom_name(monitor.name)?.reply()?.name;
+                Ok(Output {
+                    name: Some(String::from_utf8_lossy(&name).into_owned()),
+                    x: monitor.x as i32,
+                    y: monitor.y as i32,
+                    width: monitor.width as u32,
+                    height: monitor.height as u32,
+                })
+            })
+            .collect()
+    }
+
+    fn set(&mut self, wallpapers: &[(Output, RgbaImage)]) -> Result<(), Box<dyn Error>> {
+        let screen = self.screen();
+        let (root, depth) = (screen.root, screen.root_depth);
+        let (width, height) = (screen.width_in_pixels, screen.height_in_pixels);
+        if depth != 24 && depth != 32 {
+            return Err(format!("Unsupported root window depth {depth}").into());
+        }
+
+        // There is only one root window, so the wallpapers of all monitors are put together
+        let mut image = RgbaImage::new(width as u32, height as u32);
+        wallpapers.iter().for_each(|(output, wallpaper)| {
+            let wallpaper = match wallpaper.dimensions() == (output.width, output.height) {
+                true => wallpaper.clone(),
+                false => imageops::resize(
+                    wallpaper,
+                    output.width,
+                    output.height,
+                    imageops::FilterType::Triangle,
+                ),
+            };
+            imageops::replace(&mut image, &wallpaper, output.x as i64, output.y as i64);
+        });
+
+        // 24 and 32 bit visuals both store pixels in 32 bits
+        let data = image
+            .pixels()
+            .flat_map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                match self.conn.setup().image_byte_order {
+                    ImageOrder::LSB_FIRST => [b, g, r, a],
+                    _ => [a, r, g, b],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let pixmap = self.conn.generate_id()?;
+        self.conn
+            .create_pixmap(depth, pixmap, root, width, height)?;
+        let gc = self.conn.generate_id()?;
+        self.conn.create_gc(gc, pixmap, &CreateGCAux::new())?;
+
+        // Big images have to be split up to stay under the maximum request size
+        let stride = width as usize * 4;
+        let rows = ((self.conn.maximum_request_bytes() - 24) / stride).max(1);
+        data.chunks(rows * stride)
+            .enumerate()
+            .try_for_each(|(i, chunk)| {
+                self.conn.put_image(
+                    ImageFormat::Z_PIXMAP,
+                    pixmap,
+                    gc,
+                    width,
+                    (chunk.len() / stride) as u16,
+                    0,
+                    (i * rows) as i16,
+                    0,
+                    depth,
+                    chunk,
+                )?;
+                Ok::<_, Box<dyn Error>>(())
+            })?;
+        self.conn.free_gc(gc)?;
+
+        self.conn.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().background_pixmap(pixmap),
+        )?;
+        self.conn.clear_area(false, root, 0, 0, 0, 0)?;
+
+        // Compositors and pseudo transparent programs look the wallpaper up through these
+        for name in [&b"_XROOTPMAP_ID"[..], b"ESETROOT_PMAP_ID"] {
+            let atom = self.conn.intern_atom(false, name)?.reply()?.atom;
+            self.conn.change_property32(
+                PropMode::REPLACE,
+                root,
+                atom,
+                AtomEnum::PIXMAP,
+                &[pixmap],
+            )?;
+        }
+
+        if let Some(previous) = self.pixmap.replace(pixmap) {
+            self.conn.free_pixmap(previous)?;
+        }
+        self.conn.flush()?;
+
+        Ok(())
+    }
+}
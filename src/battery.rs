@@ -1,59 +1,359 @@
+use clap::ValueEnum;
 use std::{
-    fs,
+    collections::VecDeque,
+    fmt, fs, io,
     path::{Path, PathBuf},
     thread,
+    time::{Duration, Instant},
 };
 
-#[derive(PartialEq)]
+pub const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// How far back the draw is averaged over for estimates
+const RATE_HISTORY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(PartialEq, Clone, Debug, ValueEnum)]
 pub enum BatteryStatus {
     Charging,
+    Discharging,
+    /// Plugged in but not charging, e.g. held back by a charge threshold
     NotCharging,
+    Full,
+    Unknown,
 }
 
 impl BatteryStatus {
     pub fn new(status: &str) -> BatteryStatus {
         match status {
             "Charging" => Self::Charging,
-            _ => Self::NotCharging,
+            "Discharging" => Self::Discharging,
+            "Not charging" => Self::NotCharging,
+            "Full" => Self::Full,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Merges the states of several packs, any activity wins over idle packs
+    fn combine(statuses: &[BatteryStatus]) -> BatteryStatus {
+        if statuses.contains(&Self::Charging) {
+            Self::Charging
+        } else if statuses.contains(&Self::Discharging) {
+            Self::Discharging
+        } else if !statuses.is_empty() && statuses.iter().all(|status| *status == Self::Full) {
+            Self::Full
+        } else if statuses.contains(&Self::NotCharging) || statuses.contains(&Self::Full) {
+            Self::NotCharging
+        } else {
+            Self::Unknown
         }
     }
 }
 
-#[derive(PartialEq)]
+impl fmt::Display for BatteryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Charging => "charging",
+            Self::Discharging => "discharging",
+            Self::NotCharging => "not charging",
+            Self::Full => "full",
+            Self::Unknown => "unknown",
+        };
+        write!(f, "{status}")
+    }
+}
+
 pub struct Battery {
     pub status: BatteryStatus,
-    pub capacity: u8,
+    /// Percentage of charge left, fractional when the driver reports energy or charge
+    pub capacity: f32,
+    /// Remaining and full energy, or charge when the driver doesn't report energy
+    pub energy: Option<(u64, u64)>,
+    /// Power, or current when the driver doesn't report energy
+    pub rate: Option<u64>,
+    /// How long the battery lasts, in whole minutes, None when it isn't discharging or the
+    /// driver doesn't report the draw
+    pub time_to_empty: Option<Duration>,
+    /// How long until the battery is charged, in whole minutes
+    pub time_to_full: Option<Duration>,
+    /// How much of its design capacity the battery can still hold, in percent
+    pub health: Option<f32>,
+    pub cycle_count: Option<u64>,
+}
+
+/// Raw readings change with every check, only what gets shown matters. The level is compared in
+/// tenths of a percent, the energy a driver reports wobbles by less than that between checks
+impl PartialEq for Battery {
+    fn eq(&self, other: &Self) -> bool {
+        let level = |battery: &Self| (battery.capacity * 10.0).round();
+        self.status == other.status
+            && level(self) == level(other)
+            && self.time_to_empty == other.time_to_empty
+            && self.time_to_full == other.time_to_full
+    }
+}
+
+impl fmt::Display for Battery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0}% {}", self.capacity, self.status)?;
+        match (self.time_to_empty, self.time_to_full) {
+            (Some(time), _) => write!(f, ", {} left", format_duration(time)),
+            (_, Some(time)) => write!(f, ", {} until full", format_duration(time)),
+            _ => Ok(()),
+        }?;
+        if let Some(health) = self.health {
+            write!(f, ", {health:.0}% health")?;
+        }
+        match self.cycle_count {
+            Some(cycle_count) => write!(f, " after {cycle_count} cycles"),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Battery {
-    fn get_status(battery_path: &Path) -> BatteryStatus {
-        let status = fs::read_to_string(battery_path.join("status")).unwrap();
-        BatteryStatus::new(status.trim())
+    fn get_status(battery_path: &Path) -> io::Result<BatteryStatus> {
+        let status = fs::read_to_string(battery_path.join("status"))?;
+        Ok(BatteryStatus::new(status.trim()))
+    }
+
+    fn get_capacity(battery_path: &Path) -> io::Result<f32> {
+        let capacity = read_value(battery_path, "capacity")?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no capacity", battery_path.display()),
+            )
+        })?;
+        Ok((capacity as f32).min(100.0))
+    }
+
+    /// Returns the remaining and full energy of a pack, falling back to charge when the
+    /// driver doesn't report energy
+    fn get_energy(battery_path: &Path) -> io::Result<Option<(u64, u64)>> {
+        let read = |file: &str| read_value(battery_path, file);
+        match (read("energy_now")?, read("energy_full")?) {
+            (Some(now), Some(full)) => Ok(Some((now, full))),
+            _ => Ok(read("charge_now")?.zip(read("charge_full")?)),
+        }
+    }
+
+    /// Returns the draw of a pack, as power when it reports energy and as current when it
+    /// reports charge. Some drivers fail to read it at times, it's only needed for estimates
+    /// so that's not an error
+    fn get_rate(battery_path: &Path) -> Option<u64> {
+        let file = match battery_path.join("energy_now").exists() {
+            true => "power_now",
+            false => "current_now",
+        };
+        read_value(battery_path, file).ok().flatten()
+    }
+
+    /// Returns the full and design energy of a pack, falling back to charge like
+    /// [`Self::get_energy`]. Worn batteries are still usable, so failing to read these isn't
+    /// an error
+    fn get_design(battery_path: &Path) -> Option<(u64, u64)> {
+        let read = |file: &str| read_value(battery_path, file).ok().flatten();
+        match (read("energy_full"), read("energy_full_design")) {
+            (Some(full), Some(design)) => Some((full, design)),
+            _ => read("charge_full").zip(read("charge_full_design")),
+        }
+    }
+
+    fn health(design: Option<(u64, u64)>) -> Option<f32> {
+        match design {
+            Some((full, design)) if design > 0 => Some(full as f32 / design as f32 * 100.0),
+            _ => None,
+        }
+    }
+
+    /// Works out the level from the energy, which is a lot more precise than the capacity
+    fn level(energy: Option<(u64, u64)>) -> Option<f32> {
+        match energy {
+            Some((now, full)) if full > 0 => Some((now as f32 / full as f32 * 100.0).min(100.0)),
+            _ => None,
+        }
+    }
+
+    /// Works out how long the battery lasts or takes to charge at `rate`
+    fn estimate(&mut self, rate: Option<u64>) {
+        let minutes = |energy: u64| match rate {
+            Some(rate) if rate > 0 => Some(Duration::from_secs(energy * 60 / rate * 60)),
+            _ => None,
+        };
+        (self.time_to_empty, self.time_to_full) = match (&self.status, self.energy) {
+            (BatteryStatus::Discharging, Some((now, _))) => (minutes(now), None),
+            (BatteryStatus::Charging, Some((now, full))) => {
+                (None, minutes(full.saturating_sub(now)))
+            }
+            _ => (None, None),
+        };
+    }
+
+    pub fn new(battery_path: &Path) -> io::Result<Self> {
+        let energy = Self::get_energy(battery_path)?;
+        let capacity = match Self::level(energy) {
+            Some(level) => level,
+            None => Self::get_capacity(battery_path)?,
+        };
+        let mut battery = Self {
+            status: Self::get_status(battery_path)?,
+            capacity,
+            energy,
+            rate: Self::get_rate(battery_path),
+            time_to_empty: None,
+            time_to_full: None,
+            health: Self::health(Self::get_design(battery_path)),
+            cycle_count: read_value(battery_path, "cycle_count").ok().flatten(),
+        };
+        battery.estimate(battery.rate);
+        Ok(battery)
+    }
+
+    /// Combines several packs into one reading, weighting each pack by its energy
+    pub fn combined(battery_paths: &[PathBuf]) -> io::Result<Self> {
+        if let [battery_path] = battery_paths {
+            return Self::new(battery_path);
+        }
+
+        let statuses = battery_paths
+            .iter()
+            .map(|battery_path| Self::get_status(battery_path))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let energy = battery_paths
+            .iter()
+            .map(|battery_path| Self::get_energy(battery_path))
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .map(|energy| {
+                energy
+                    .iter()
+                    .fold((0, 0), |(now, full), pack| (now + pack.0, full + pack.1))
+            });
+        let rate = battery_paths
+            .iter()
+            .map(|battery_path| Self::get_rate(battery_path))
+            .sum::<Option<u64>>();
+
+        let capacity = match Self::level(energy) {
+            Some(level) => level,
+            // Not every driver exposes energy, an average is the best we can do then
+            None if !battery_paths.is_empty() => {
+                let sum = battery_paths
+                    .iter()
+                    .map(|battery_path| Self::get_capacity(battery_path))
+                    .sum::<io::Result<f32>>()?;
+                sum / battery_paths.len() as f32
+            }
+            None => 0.0,
+        };
+
+        let design = battery_paths
+            .iter()
+            .map(|battery_path| Self::get_design(battery_path))
+            .try_fold((0, 0), |(full, design), pack| {
+                Some((full + pack?.0, design + pack?.1))
+            });
+        // The most worn pack says the most about how the battery is doing
+        let cycle_count = battery_paths
+            .iter()
+            .filter_map(|battery_path| read_value(battery_path, "cycle_count").ok().flatten())
+            .max();
+
+        let mut battery = Self {
+            status: BatteryStatus::combine(&statuses),
+            capacity,
+            energy,
+            rate,
+            time_to_empty: None,
+            time_to_full: None,
+            health: Self::health(design),
+            cycle_count,
+        };
+        battery.estimate(rate);
+        Ok(battery)
     }
+}
 
-    fn get_capacity(battery_path: &Path) -> u8 {
-        fs::read_to_string(battery_path.join("capacity"))
-            .unwrap()
-            .trim()
-            .parse::<u8>()
-            .unwrap_or(0)
+/// Recent draw of a battery, averaged so estimates don't jump around with every spike
+#[derive(Default)]
+pub struct RateHistory {
+    status: Option<BatteryStatus>,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateHistory {
+    /// Adds the draw of the battery to the history and estimates its times from the average
+    pub fn update(&mut self, battery: &mut Battery) {
+        // Charging and discharging rates have nothing to do with each other
+        if self.status.as_ref() != Some(&battery.status) {
+            self.samples.clear();
+            self.status = Some(battery.status.clone());
+        }
+
+        let now = Instant::now();
+        if let Some(rate) = battery.rate {
+            self.samples.push_back((now, rate));
+        }
+        while matches!(self.samples.front(), Some((time, _)) if now - *time > RATE_HISTORY) {
+            self.samples.pop_front();
+        }
+
+        let average = match self.samples.len() {
+            0 => None,
+            len => Some(self.samples.iter().map(|(_, rate)| rate).sum::<u64>() / len as u64),
+        };
+        battery.estimate(average);
     }
+}
 
-    pub fn new(battery_path: &Path) -> Self {
-        let status = Self::get_status(battery_path);
-        let capacity = Self::get_capacity(battery_path);
-        Self { status, capacity }
+/// Formats a duration like 2h 14m
+pub fn format_duration(time: Duration) -> String {
+    let minutes = time.as_secs() / 60;
+    match minutes / 60 {
+        0 => format!("{}m", minutes % 60),
+        hours => format!("{hours}h {}m", minutes % 60),
     }
 }
 
-pub fn find_battery_path() -> Option<PathBuf> {
-    fs::read_dir("/sys/class/power_supply")
-        .ok()?
+/// Reads a number from one of the files of a pack, None if the driver doesn't have the file.
+/// Some drivers report the draw as negative while discharging, so only its size is kept
+fn read_value(battery_path: &Path, file: &str) -> io::Result<Option<u64>> {
+    let path = battery_path.join(file);
+    let value = match fs::read_to_string(&path) {
+        Ok(value) => value,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(io::Error::new(
+                err.kind(),
+                format!("Failed to read {}: {err}", path.display()),
+            ))
+        }
+    };
+
+    value
+        .trim()
+        .parse::<i64>()
+        .map(|value| Some(value.unsigned_abs()))
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has an invalid value {:?}", path.display(), value.trim()),
+            )
+        })
+}
+
+/// Lists the batteries found under `root`, normally [`POWER_SUPPLY`]. Batteries of mice,
+/// keyboards and other peripherals are left out, they have nothing to do with the system's
+pub fn find_battery_paths(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(root)?
         .map(|entry| {
             let path = entry.ok()?.path();
             let handle = thread::spawn(move || {
                 let file_content = fs::read_to_string(path.join("type")).ok()?;
+                let scope = fs::read_to_string(path.join("scope")).unwrap_or_default();
                 if file_content.trim() == "Battery"
+                    && scope.trim() != "Device"
                     && path.join("status").exists()
                     && path.join("capacity").exists()
                 {
@@ -64,5 +364,201 @@ pub fn find_battery_path() -> Option<PathBuf> {
             });
             Some(handle)
         })
-        .find_map(|handle| handle?.join().ok()?)
+        .filter_map(|handle| handle?.join().ok()?)
+        .collect::<Vec<_>>();
+
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// Power supply directory made up of packs, each a list of files and their contents
+    struct FakeTree(PathBuf);
+
+    impl FakeTree {
+        fn new(name: &str, packs: &[(&str, &[(&str, &str)])]) -> Self {
+            let root = env::temp_dir().join(format!("ruin-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&root);
+            packs.iter().for_each(|(pack, files)| {
+                fs::create_dir_all(root.join(pack)).unwrap();
+                files.iter().for_each(|(file, content)| {
+                    fs::write(root.join(pack).join(file), format!("{content}\n")).unwrap()
+                });
+            });
+            Self(root)
+        }
+
+        fn paths(&self) -> Vec<PathBuf> {
+            find_battery_paths(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for FakeTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn finds_system_batteries() {
+        let tree = FakeTree::new(
+            "find",
+            &[
+                (
+                    "BAT1",
+                    &[("type", "Battery"), ("status", "Full"), ("capacity", "100")],
+                ),
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("scope", "System"),
+                        ("status", "Discharging"),
+                        ("capacity", "40"),
+                    ],
+                ),
+                ("AC", &[("type", "Mains"), ("online", "0")]),
+                (
+                    "hidpp_battery_0",
+                    &[
+                        ("type", "Battery"),
+                        ("scope", "Device"),
+                        ("status", "Discharging"),
+                        ("capacity", "100"),
+                    ],
+                ),
+                ("BAT2", &[("type", "Battery"), ("status", "Unknown")]),
+            ],
+        );
+
+        assert_eq!(tree.paths(), [tree.0.join("BAT0"), tree.0.join("BAT1")]);
+    }
+
+    #[test]
+    fn reads_energy() {
+        let tree = FakeTree::new(
+            "energy",
+            &[(
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("status", "Discharging"),
+                    ("capacity", "49"),
+                    ("energy_now", "30000000"),
+                    ("energy_full", "60000000"),
+                    ("energy_full_design", "80000000"),
+                    ("power_now", "-10000000"),
+                    ("cycle_count", "120"),
+                ],
+            )],
+        );
+        let battery = Battery::new(&tree.paths()[0]).unwrap();
+
+        assert_eq!(battery.status, BatteryStatus::Discharging);
+        assert_eq!(battery.capacity, 50.0);
+        assert_eq!(
+            battery.time_to_empty,
+            Some(Duration::from_secs(3 * 60 * 60))
+        );
+        assert_eq!(battery.time_to_full, None);
+        assert_eq!(battery.health, Some(75.0));
+        assert_eq!(battery.cycle_count, Some(120));
+    }
+
+    #[test]
+    fn combines_packs() {
+        let tree = FakeTree::new(
+            "combined",
+            &[
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Discharging"),
+                        ("capacity", "25"),
+                        ("energy_now", "10000000"),
+                        ("energy_full", "40000000"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Not charging"),
+                        ("capacity", "50"),
+                        ("energy_now", "50000000"),
+                        ("energy_full", "80000000"),
+                    ],
+                ),
+            ],
+        );
+        let battery = Battery::combined(&tree.paths()).unwrap();
+
+        // Weighted by energy, the bigger pack counts more
+        assert_eq!(battery.capacity, 50.0);
+        assert_eq!(battery.status, BatteryStatus::Discharging);
+        assert_eq!(battery.energy, Some((60000000, 120000000)));
+    }
+
+    #[test]
+    fn averages_capacity_without_energy() {
+        let tree = FakeTree::new(
+            "average",
+            &[
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Charging"),
+                        ("capacity", "20"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[("type", "Battery"), ("status", "Full"), ("capacity", "100")],
+                ),
+            ],
+        );
+        let battery = Battery::combined(&tree.paths()).unwrap();
+
+        assert_eq!(battery.capacity, 60.0);
+        assert_eq!(battery.status, BatteryStatus::Charging);
+        assert_eq!(battery.energy, None);
+    }
+
+    #[test]
+    fn reports_unreadable_capacity() {
+        let tree = FakeTree::new(
+            "unreadable",
+            &[
+                (
+                    "BAT0",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Discharging"),
+                        ("capacity", "n/a"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[
+                        ("type", "Battery"),
+                        ("status", "Discharging"),
+                        ("capacity", "80"),
+                    ],
+                ),
+            ],
+        );
+        let paths = tree.paths();
+
+        let err = Battery::new(&paths[0]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("capacity"));
+        assert!(Battery::combined(&paths).is_err());
+        assert!(Battery::new(&paths[1]).is_ok());
+    }
 }
@@ -0,0 +1,430 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{cell::RefCell, collections::HashMap, fmt, str::FromStr};
+
+thread_local! {
+    /// Names that can be used as colors while a colorscheme with a palette is read
+    static PALETTE: RefCell<HashMap<String, Color>> = RefCell::default();
+}
+
+/// Lets the names in `palette` be used as colors while `f` deserializes
+pub fn with_palette<T>(palette: HashMap<String, Color>, f: impl FnOnce() -> T) -> T {
+    PALETTE.with(|current| current.replace(palette));
+    let result = f();
+    PALETTE.with(|current| current.take());
+    result
+}
+
+/// A color with an alpha channel, written as `[r, g, b]`, `[r, g, b, a]`, `"#rrggbb"`,
+/// `"#rrggbbaa"`, `"rgb(r, g, b)"`, `"hsl(h, s%, l%)"` or a CSS color name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub [u8; 4]);
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self([r, g, b, 255])
+    }
+
+    pub fn channels(self) -> [u8; 3] {
+        let [r, g, b, _] = self.0;
+        [r, g, b]
+    }
+
+    pub fn alpha(self) -> u8 {
+        self.0[3]
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(color: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("{color} is not a color like #8fbcbb, rgb(143, 188, 187), hsl(178, 25%, 65%) or cadetblue")
+        };
+        let lower = color.trim().to_ascii_lowercase();
+
+        if let Some(hex) = lower.strip_prefix('#') {
+            return parse_hex(hex).ok_or_else(invalid);
+        }
+        if let Some((function, args)) = lower
+            .strip_suffix(')')
+            .and_then(|color| color.split_once('('))
+        {
+            // Both the old comma separated and the newer space separated syntax, with the alpha
+            // after a slash
+            let args = args
+                .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .collect::<Vec<_>>();
+            let (args, alpha) = match args.len() {
+                3 => (&args[..], 255),
+                4 => (&args[..3], parse_alpha(args[3]).ok_or_else(invalid)?),
+                _ => return Err(invalid()),
+            };
+            let [r, g, b] = match function.trim() {
+                "rgb" | "rgba" => [
+                    parse_channel(args[0]),
+                    parse_channel(args[1]),
+                    parse_channel(args[2]),
+                ],
+                "hsl" | "hsla" => {
+                    let hue = args[0].strip_suffix("deg").unwrap_or(args[0]);
+                    match (
+                        hue.parse::<f32>().ok(),
+                        parse_percentage(args[1]),
+                        parse_percentage(args[2]),
+                    ) {
+                        (Some(hue), Some(saturation), Some(lightness)) => {
+                            from_hsl(hue, saturation, lightness).map(Some)
+                        }
+                        _ => [None; 3],
+                    }
+                }
+                _ => [None; 3],
+            }
+            .map(|channel| channel.ok_or_else(invalid));
+
+            return Ok(Self([r?, g?, b?, alpha]));
+        }
+        if lower == "transparent" {
+            return Ok(Self([0, 0, 0, 0]));
+        }
+
+        NAMES
+            .iter()
+            .find(|(name, _)| *name == lower)
+            .map(|(_, [r, g, b])| Self::rgb(*r, *g, *b))
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        match a {
+            255 => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+            _ => write!(f, "#{r:02x}{g:02x}{b:02x}{a:02x}"),
+        }
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Color;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a color like [143, 188, 187], \"#8fbcbb\", \"rgb(143, 188, 187)\", \"hsl(178, 25%, 65%)\" or \"cadetblue\"")
+            }
+
+            fn visit_str<E: de::Error>(self, color: &str) -> Result<Color, E> {
+                color
+                    .parse()
+                    .or_else(|err| {
+                        PALETTE.with(|palette| match palette.borrow().is_empty() {
+                            true => Err(err),
+                            false => palette.borrow().get(color).copied().ok_or_else(|| {
+                                format!("{color} is neither a color nor a name from the palette")
+                            }),
+                        })
+                    })
+                    .map_err(E::custom)
+            }
+
+            // The original format, an array of three channels, or four with alpha
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Color, A::Error> {
+                let mut channels = [255; 4];
+                let mut len = 0;
+                while let Some(channel) = seq.next_element()? {
+                    if len == 4 {
+                        return Err(de::Error::invalid_length(5, &self));
+                    }
+                    channels[len] = channel;
+                    len += 1;
+                }
+                match len {
+                    3 | 4 => Ok(Color(channels)),
+                    _ => Err(de::Error::invalid_length(len, &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.is_ascii() {
+        return None;
+    }
+    // Short forms repeat each digit, #abc is #aabbcc
+    let digits = match hex.len() {
+        3 | 4 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+        6 | 8 => hex.to_string(),
+        _ => return None,
+    };
+    let mut channels = [255; 4];
+    for (i, channel) in channels.iter_mut().take(digits.len() / 2).enumerate() {
+        *channel = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(Color(channels))
+}
+
+/// A channel from 0 to 255 or a percentage
+fn parse_channel(channel: &str) -> Option<u8> {
+    let value = match channel.strip_suffix('%') {
+        Some(percentage) => percentage.parse::<f32>().ok()? * 2.55,
+        None => channel.parse::<f32>().ok()?,
+    };
+    (0.0..=255.0).contains(&value).then(|| value.round() as u8)
+}
+
+/// An alpha from 0 to 1 or a percentage
+fn parse_alpha(alpha: &str) -> Option<u8> {
+    let value = match alpha.strip_suffix('%') {
+        Some(percentage) => percentage.parse::<f32>().ok()? / 100.0,
+        None => alpha.parse::<f32>().ok()?,
+    };
+    (0.0..=1.0)
+        .contains(&value)
+        .then(|| (value * 255.0).round() as u8)
+}
+
+/// A percentage from 0 to 100 as a fraction, the % sign can be left out
+fn parse_percentage(percentage: &str) -> Option<f32> {
+    let value = percentage
+        .strip_suffix('%')
+        .unwrap_or(percentage)
+        .parse::<f32>()
+        .ok()?;
+    (0.0..=100.0).contains(&value).then_some(value / 100.0)
+}
+
+// From https://www.w3.org/TR/css-color-4/#hsl-to-rgb
+fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> [u8; 3] {
+    let hue = hue.rem_euclid(360.0);
+    let channel = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        let a = saturation * lightness.min(1.0 - lightness);
+        let value = lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+        (value * 255.0).round() as u8
+    };
+    [channel(0.0), channel(8.0), channel(4.0)]
+}
+
+/// The named colors of CSS
+const NAMES: [(&str, [u8; 3]); 148] = [
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(color: &str) -> [u8; 4] {
+        color.parse::<Color>().unwrap().0
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(parse("#8fbcbb"), [143, 188, 187, 255]);
+        assert_eq!(parse("#abc"), [0xaa, 0xbb, 0xcc, 255]);
+        assert_eq!(parse("#ABCD"), [0xaa, 0xbb, 0xcc, 0xdd]);
+        assert_eq!(parse("#8fbcbb80"), [143, 188, 187, 128]);
+        assert_eq!(Color([143, 188, 187, 128]).to_string(), "#8fbcbb80");
+        assert!("#8fbcb".parse::<Color>().is_err());
+        assert!("#ggg".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn parses_functions() {
+        assert_eq!(parse("rgb(143, 188, 187)"), [143, 188, 187, 255]);
+        assert_eq!(parse("rgb(100%, 50%, 0%)"), [255, 128, 0, 255]);
+        assert_eq!(parse("rgba(255, 0, 0, 0.5)"), [255, 0, 0, 128]);
+        assert_eq!(parse("rgb(255 0 0 / 25%)"), [255, 0, 0, 64]);
+        assert!("rgb(256, 0, 0)".parse::<Color>().is_err());
+        assert!("rgb(0, 0)".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn wraps_hue() {
+        assert_eq!(parse("hsl(120, 100%, 50%)"), [0, 255, 0, 255]);
+        assert_eq!(parse("hsl(480, 100%, 50%)"), [0, 255, 0, 255]);
+        assert_eq!(parse("hsl(-240deg 100% 50%)"), [0, 255, 0, 255]);
+        assert_eq!(parse("hsla(360, 100%, 50%, 1)"), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(parse("CadetBlue"), [95, 158, 160, 255]);
+        assert_eq!(parse("transparent"), [0, 0, 0, 0]);
+        assert!("notacolor".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn deserializes_arrays() {
+        let color = |yaml: &str| serde_yaml::from_str::<Color>(yaml).map(|color| color.0);
+        assert_eq!(color("[143, 188, 187]").unwrap(), [143, 188, 187, 255]);
+        assert_eq!(color("[143, 188, 187, 128]").unwrap(), [143, 188, 187, 128]);
+        assert_eq!(color("\"#8fbcbb\"").unwrap(), [143, 188, 187, 255]);
+        assert!(color("[143, 188]").is_err());
+        assert!(color("[143, 188, 187, 128, 0]").is_err());
+        assert!(color("[256, 0, 0]").is_err());
+    }
+}
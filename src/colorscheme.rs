@@ -0,0 +1,673 @@
+use crate::{
+    battery::{Battery, BatteryStatus},
+    color::{self, Color},
+};
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use serde_path_to_error::Track;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub charging: Color,
+    /// Used when the battery is full, default if not given
+    pub full: Option<Color>,
+    /// Used when plugged in but not charging, default if not given
+    pub plugged_idle: Option<Color>,
+    /// Used on battery when the capacity is above every threshold
+    pub default: Color,
+    /// Used below the low_battery capacity from the config when there are no thresholds
+    pub low_battery: Color,
+    pub thresholds: Vec<Threshold>,
+    /// Fades between these stops across the whole capacity range, replaces every other fill
+    /// color when given
+    pub gradient: Vec<Stop>,
+    pub background: Color,
+    /// Used for the capacity a worn battery has lost, when health is shown
+    pub lost_capacity: Color,
+    /// File whose colors can be used by name, like the colors.json of pywal or a base16 scheme
+    pub palette: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawThreshold")]
+pub struct Threshold {
+    /// Capacity below which the color is used
+    pub below: Option<u8>,
+    /// Minutes of battery left below which the color is used
+    pub minutes_below: Option<u64>,
+    pub color: Color,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawThreshold {
+    #[serde(default)]
+    below: Option<u8>,
+    #[serde(default)]
+    minutes_below: Option<u64>,
+    color: Color,
+}
+
+/// A threshold without either limit would never be used
+impl TryFrom<RawThreshold> for Threshold {
+    type Error = &'static str;
+
+    fn try_from(threshold: RawThreshold) -> Result<Self, Self::Error> {
+        let RawThreshold {
+            below,
+            minutes_below,
+            color,
+        } = threshold;
+        match (below, minutes_below) {
+            (None, None) => Err("a threshold needs below or minutes_below"),
+            _ => Ok(Self {
+                below,
+                minutes_below,
+                color,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stop {
+    /// Capacity at which the color is reached
+    pub at: u8,
+    pub color: Color,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            charging: Color::rgb(255, 255, 0),
+            full: None,
+            plugged_idle: None,
+            default: Color::rgb(91, 194, 54),
+            low_battery: Color::rgb(191, 19, 28),
+            thresholds: Vec::new(),
+            gradient: Vec::new(),
+            background: Color::rgb(40, 40, 40),
+            lost_capacity: Color::rgb(105, 105, 105),
+            palette: None,
+        }
+    }
+}
+
+impl Colors {
+    pub fn fill(&self, battery: &Battery, low_battery: u8) -> Color {
+        if !self.gradient.is_empty() {
+            return self.gradient(battery.capacity);
+        }
+
+        match battery.status {
+            BatteryStatus::Charging => self.charging,
+            BatteryStatus::Full => self.full.unwrap_or(self.default),
+            BatteryStatus::NotCharging => self.plugged_idle.unwrap_or(self.default),
+            _ => self.on_battery(battery, low_battery),
+        }
+    }
+
+    /// Interpolates between the two stops around the capacity in OKLab, so the fade looks even
+    /// instead of going through the muddy colors RGB interpolation produces
+    fn gradient(&self, capacity: f32) -> Color {
+        let mut stops = self.gradient.iter().collect::<Vec<_>>();
+        stops.sort_by_key(|stop| stop.at);
+
+        let upper = stops.iter().position(|stop| stop.at as f32 >= capacity);
+        let (from, to) = match upper {
+            Some(0) => return stops[0].color,
+            Some(i) => (stops[i - 1], stops[i]),
+            None => return stops[stops.len() - 1].color,
+        };
+
+        let t = (capacity - from.at as f32) as f64 / (to.at - from.at) as f64;
+        let alpha =
+            from.color.alpha() as f64 + (to.color.alpha() as f64 - from.color.alpha() as f64) * t;
+        let (from, to) = (
+            to_oklab(from.color.channels()),
+            to_oklab(to.color.channels()),
+        );
+        let [r, g, b] = from_oklab([
+            from[0] + (to[0] - from[0]) * t,
+            from[1] + (to[1] - from[1]) * t,
+            from[2] + (to[2] - from[2]) * t,
+        ]);
+        Color([r, g, b, alpha.round() as u8])
+    }
+
+    /// Picks the color of the lowest threshold the capacity is below, colorschemes without
+    /// thresholds only switch between default and low_battery at `low_battery`. Thresholds on
+    /// the time left go first, as they know about the current draw
+    pub fn on_battery(&self, battery: &Battery, low_battery: u8) -> Color {
+        if self.thresholds.is_empty() {
+            return match battery.capacity >= low_battery as f32 {
+                true => self.default,
+                false => self.low_battery,
+            };
+        }
+
+        let minutes_left = battery.time_to_empty.map(|time| time.as_secs() / 60);
+        let by_time = self
+            .thresholds
+            .iter()
+            .filter_map(|threshold| Some((threshold.minutes_below?, threshold.color)))
+            .filter(|(minutes, _)| minutes_left.is_some_and(|left| left < *minutes))
+            .min_by_key(|(minutes, _)| *minutes)
+            .map(|(_, color)| color);
+        let by_capacity = self
+            .thresholds
+            .iter()
+            .filter_map(|threshold| Some((threshold.below?, threshold.color)))
+            .filter(|(below, _)| battery.capacity < *below as f32)
+            .min_by_key(|(below, _)| *below)
+            .map(|(_, color)| color);
+
+        by_time.or(by_capacity).unwrap_or(self.default)
+    }
+}
+
+/// Why colorschemes.yaml couldn't give the colors for an image
+#[derive(Debug)]
+pub enum ColorschemeError {
+    Read {
+        path: PathBuf,
+        err: io::Error,
+    },
+    /// The file isn't valid YAML or one of its values is wrong, located as closely as serde_yaml
+    /// allows
+    Parse {
+        path: PathBuf,
+        /// Where the value is, e.g. example.thresholds[0].color
+        key: Option<String>,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// The file has no colorscheme with the name of the image
+    Missing {
+        path: PathBuf,
+        name: String,
+        available: Vec<String>,
+    },
+    /// The palette the colorscheme takes its colors from is missing or has no colors in it
+    Palette {
+        path: PathBuf,
+        name: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ColorschemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read { path, err } => write!(f, "Failed to read {}: {err}", path.display()),
+            Self::Parse {
+                path,
+                key,
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "Invalid colorscheme {}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{line}")?;
+                }
+                if let Some(column) = column {
+                    write!(f, ":{column}")?;
+                }
+                if let Some(key) = key {
+                    write!(f, " at {key}")?;
+                }
+                write!(f, ": {message}")
+            }
+            Self::Missing {
+                path,
+                name,
+                available,
+            } => {
+                write!(f, "No colorscheme named {name} in {}", path.display())?;
+                match available.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, ", it has {}", available.join(", ")),
+                }
+            }
+            Self::Palette {
+                path,
+                name,
+                message,
+            } => write!(
+                f,
+                "Failed to load the palette of colorscheme {name} from {}: {message}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for ColorschemeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Read { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the colorscheme called `name` from colorschemes.yaml, none when there's no such file
+pub fn get_colorscheme(path: &Path, name: &str) -> Result<Option<Colors>, ColorschemeError> {
+    let ruin_dir = path;
+    let path = path.join("colorschemes.yaml");
+    let file = match fs::read_to_string(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(ColorschemeError::Read { path, err }),
+    };
+    if file.trim().is_empty() {
+        return Ok(None);
+    }
+
+    // The palette has to be loaded before the colors that use its names can be read
+    let palette = match find_palette(ruin_dir, &file, name) {
+        Some(palette_path) => {
+            load_palette(&palette_path).map_err(|message| ColorschemeError::Palette {
+                path: palette_path,
+                name: name.to_string(),
+                message,
+            })?
+        }
+        None => HashMap::new(),
+    };
+    let mut available = Vec::new();
+    let colors = color::with_palette(palette, || read_scheme(&file, name, &mut available))
+        .map_err(|err| parse_error(&path, err))?;
+
+    match colors {
+        Some(colors) => Ok(Some(colors)),
+        None => {
+            available.sort();
+            Err(ColorschemeError::Missing {
+                path,
+                name: name.to_string(),
+                available,
+            })
+        }
+    }
+}
+
+/// Where the palette of the colorscheme called `name` is, if it has one, so it can be watched
+pub fn palette_path(ruin_dir: &Path, name: &str) -> Option<PathBuf> {
+    let file = fs::read_to_string(ruin_dir.join("colorschemes.yaml")).ok()?;
+    find_palette(ruin_dir, &file, name)
+}
+
+fn find_palette(ruin_dir: &Path, file: &str, name: &str) -> Option<PathBuf> {
+    #[derive(Deserialize)]
+    struct PaletteOnly {
+        palette: Option<PathBuf>,
+    }
+
+    // Anything wrong with the colorscheme is reported once it's read in full
+    let palette = read_scheme::<PaletteOnly>(file, name, &mut Vec::new())
+        .ok()??
+        .palette?;
+    Some(match (palette.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(palette), Some(home_dir)) => home_dir.join(palette),
+        _ => ruin_dir.join(palette),
+    })
+}
+
+/// Picks the colors out of a palette file, these are all the values written in hex. That covers
+/// the colors.json of pywal, base16 schemes and most other themes
+fn load_palette(path: &Path) -> Result<HashMap<String, Color>, String> {
+    fn collect(value: &serde_yaml::Value, colors: &mut HashMap<String, Color>) {
+        let Some(mapping) = value.as_mapping() else {
+            return;
+        };
+        mapping.iter().for_each(|(key, value)| match (key, value) {
+            // Colors are often grouped, like pywal's colors and special
+            (_, serde_yaml::Value::Mapping(_)) => collect(value, colors),
+            (serde_yaml::Value::String(key), serde_yaml::Value::String(value)) => {
+                let hex = value.strip_prefix('#').unwrap_or(value);
+                let is_hex =
+                    matches!(hex.len(), 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit());
+                if let (true, Ok(color)) = (is_hex, format!("#{hex}").parse()) {
+                    colors.insert(key.clone(), color);
+                }
+            }
+            _ => {}
+        });
+    }
+
+    let file = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    // JSON is YAML as well
+    let palette: serde_yaml::Value = serde_yaml::from_str(&file).map_err(|err| err.to_string())?;
+    let mut colors = HashMap::new();
+    collect(&palette, &mut colors);
+    match colors.is_empty() {
+        true => Err("there are no colors like #8fbcbb in it".to_string()),
+        false => Ok(colors),
+    }
+}
+
+/// Reads just the colorscheme called `name`, the names of the others are put into `others`
+fn read_scheme<T: DeserializeOwned>(
+    file: &str,
+    name: &str,
+    others: &mut Vec<String>,
+) -> Result<Option<T>, serde_path_to_error::Error<serde_yaml::Error>> {
+    struct Scheme<'a, T> {
+        name: &'a str,
+        others: &'a mut Vec<String>,
+        scheme: PhantomData<T>,
+    }
+
+    impl<'de, T: DeserializeOwned> DeserializeSeed<'de> for Scheme<'_, T> {
+        type Value = Option<T>;
+
+        fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Option<T>, D::Error> {
+            deserializer.deserialize_map(self)
+        }
+    }
+
+    impl<'de, T: DeserializeOwned> Visitor<'de> for Scheme<'_, T> {
+        type Value = Option<T>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("colorschemes by name")
+        }
+
+        // The other colorschemes may use names from palettes of their own, so they're skipped
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Option<T>, A::Error> {
+            let mut scheme = None;
+            while let Some(key) = map.next_key::<String>()? {
+                match key == self.name {
+                    true => scheme = Some(map.next_value()?),
+                    false => {
+                        map.next_value::<IgnoredAny>()?;
+                        self.others.push(key);
+                    }
+                }
+            }
+            Ok(scheme)
+        }
+    }
+
+    let mut track = Track::new();
+    let deserializer = serde_path_to_error::Deserializer::new(
+        serde_yaml::Deserializer::from_str(file),
+        &mut track,
+    );
+    Scheme {
+        name,
+        others,
+        scheme: PhantomData,
+    }
+    .deserialize(deserializer)
+    .map_err(|err| serde_path_to_error::Error::new(track.path(), err))
+}
+
+fn parse_error(
+    path: &Path,
+    err: serde_path_to_error::Error<serde_yaml::Error>,
+) -> ColorschemeError {
+    let key = Some(err.path().to_string()).filter(|key| key != ".");
+    let location = err.inner().location();
+
+    // serde_yaml puts a key and the location into its message too, they're reported on their own
+    // instead. Its key can be a parent of the one that failed, e.g. for unknown fields
+    let mut message = err.inner().to_string();
+    if let Some(location) = &location {
+        let at = format!(" at line {} column {}", location.line(), location.column());
+        message = message.replacen(&at, "", 1);
+    }
+    if let Some((prefix, rest)) = message.split_once(": ") {
+        if key.as_ref().is_some_and(|key| key.starts_with(prefix)) {
+            message = rest.to_string();
+        }
+    }
+
+    ColorschemeError::Parse {
+        path: path.to_path_buf(),
+        key,
+        line: location.as_ref().map(|location| location.line()),
+        column: location.as_ref().map(|location| location.column()),
+        message,
+    }
+}
+
+// Conversions from https://bottosson.github.io/posts/oklab/
+fn to_oklab(color: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = color.map(|channel| {
+        let channel = channel as f64 / 255.0;
+        match channel <= 0.04045 {
+            true => channel / 12.92,
+            false => ((channel + 0.055) / 1.055).powf(2.4),
+        }
+    });
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn from_oklab(color: [f64; 3]) -> [u8; 3] {
+    let [l, a, b] = color;
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+    ]
+    .map(|channel| {
+        let channel = match channel <= 0.0031308 {
+            true => channel * 12.92,
+            false => 1.055 * channel.powf(1.0 / 2.4) - 0.055,
+        };
+        (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// A config directory with the given colorschemes.yaml
+    struct RuinDir(PathBuf);
+
+    impl RuinDir {
+        fn new(name: &str, colorschemes: &str) -> Self {
+            let dir = env::temp_dir().join(format!("ruin-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("colorschemes.yaml"), colorschemes).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for RuinDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn battery(status: BatteryStatus, capacity: f32) -> Battery {
+        Battery {
+            status,
+            capacity,
+            energy: None,
+            rate: None,
+            time_to_empty: None,
+            time_to_full: None,
+            health: None,
+            cycle_count: None,
+        }
+    }
+
+    #[test]
+    fn idle_colors_fall_back_to_default() {
+        let colors: Colors =
+            serde_yaml::from_str("charging: yellow\ndefault: \"#5bc236\"\nlow_battery: red")
+                .unwrap();
+        let default = Color::rgb(0x5b, 0xc2, 0x36);
+
+        assert_eq!(
+            colors.fill(&battery(BatteryStatus::Full, 100.0), 30),
+            default
+        );
+        assert_eq!(
+            colors.fill(&battery(BatteryStatus::NotCharging, 80.0), 30),
+            default
+        );
+        assert_eq!(
+            colors.fill(&battery(BatteryStatus::Charging, 80.0), 30),
+            Color::rgb(255, 255, 0)
+        );
+
+        let colors: Colors = serde_yaml::from_str("full: blue\nplugged_idle: white").unwrap();
+        assert_eq!(
+            colors.fill(&battery(BatteryStatus::Full, 100.0), 30),
+            Color::rgb(0, 0, 255)
+        );
+        assert_eq!(
+            colors.fill(&battery(BatteryStatus::NotCharging, 80.0), 30),
+            Color::rgb(255, 255, 255)
+        );
+    }
+
+    #[test]
+    fn rejects_thresholds_without_limit() {
+        let dir = RuinDir::new(
+            "threshold",
+            "test:\n  thresholds:\n    - below: 10\n      color: red\n    - color: red\n",
+        );
+        match get_colorscheme(&dir.0, "test") {
+            Err(ColorschemeError::Parse {
+                key, line, message, ..
+            }) => {
+                assert_eq!(key.as_deref(), Some("test.thresholds[1]"));
+                assert!(line.is_some());
+                assert_eq!(message, "a threshold needs below or minutes_below");
+            }
+            other => panic!("{other:?}"),
+        }
+
+        let dir = RuinDir::new(
+            "threshold-minutes",
+            "test:\n  thresholds:\n    - minutes_below: 20\n      color: red\n",
+        );
+        let colors = get_colorscheme(&dir.0, "test").unwrap().unwrap();
+        assert_eq!(colors.thresholds[0].minutes_below, Some(20));
+    }
+
+    #[test]
+    fn finds_palettes() {
+        let dir = RuinDir::new(
+            "find-palette",
+            "home:\n  palette: ~/.cache/wal/colors.json\nrelative:\n  palette: themes/base16.yaml\nabsolute:\n  palette: /etc/colors.json\nnone:\n  default: red\n",
+        );
+        let file = fs::read_to_string(dir.0.join("colorschemes.yaml")).unwrap();
+
+        if let Some(home_dir) = dirs::home_dir() {
+            assert_eq!(
+                find_palette(&dir.0, &file, "home"),
+                Some(home_dir.join(".cache/wal/colors.json"))
+            );
+        }
+        assert_eq!(
+            find_palette(&dir.0, &file, "relative"),
+            Some(dir.0.join("themes/base16.yaml"))
+        );
+        assert_eq!(
+            palette_path(&dir.0, "absolute"),
+            Some(PathBuf::from("/etc/colors.json"))
+        );
+        assert_eq!(find_palette(&dir.0, &file, "none"), None);
+        assert_eq!(find_palette(&dir.0, &file, "missing"), None);
+    }
+
+    #[test]
+    fn loads_palettes() {
+        let dir = RuinDir::new("load-palette", "");
+        let pywal = dir.0.join("colors.json");
+        fs::write(
+            &pywal,
+            r##"{
+                "wallpaper": "/home/user/wallpaper.png",
+                "alpha": "100",
+                "special": {"background": "#282828", "foreground": "#EBDBB2"},
+                "colors": {"color1": "#cc241d", "color2": "#98971a"}
+            }"##,
+        )
+        .unwrap();
+        let colors = load_palette(&pywal).unwrap();
+        assert_eq!(colors.len(), 4);
+        assert_eq!(colors["background"], Color::rgb(0x28, 0x28, 0x28));
+        assert_eq!(colors["foreground"], Color::rgb(0xeb, 0xdb, 0xb2));
+        assert_eq!(colors["color2"], Color::rgb(0x98, 0x97, 0x1a));
+
+        let base16 = dir.0.join("base16.yaml");
+        fs::write(
+            &base16,
+            "scheme: \"Gruvbox dark, hard\"\nauthor: \"Dawid Kurek\"\nbase00: \"1d2021\"\nbase08: \"fb4934\"\nbase0B: \"b8bb2680\"\n",
+        )
+        .unwrap();
+        let colors = load_palette(&base16).unwrap();
+        assert_eq!(colors.len(), 3);
+        assert_eq!(colors["base00"], Color::rgb(0x1d, 0x20, 0x21));
+        assert_eq!(colors["base0B"], Color([0xb8, 0xbb, 0x26, 0x80]));
+
+        fs::write(&base16, "scheme: empty\n").unwrap();
+        assert!(load_palette(&base16).is_err());
+        assert!(load_palette(&dir.0.join("missing.yaml")).is_err());
+    }
+
+    #[test]
+    fn colors_are_named_from_the_palette() {
+        let dir = RuinDir::new(
+            "palette-names",
+            "example:\n  palette: themes/base16.yaml\n  default: base0B\n  low_battery: red\n  background: base00\nunknown:\n  palette: themes/base16.yaml\n  default: base0F\n",
+        );
+        fs::create_dir_all(dir.0.join("themes")).unwrap();
+        fs::write(
+            dir.0.join("themes/base16.yaml"),
+            "base00: \"1d2021\"\nbase0B: \"b8bb26\"\n",
+        )
+        .unwrap();
+
+        let colors = get_colorscheme(&dir.0, "example").unwrap().unwrap();
+        assert_eq!(colors.default, Color::rgb(0xb8, 0xbb, 0x26));
+        assert_eq!(colors.low_battery, Color::rgb(255, 0, 0));
+        assert_eq!(colors.background, Color::rgb(0x1d, 0x20, 0x21));
+        assert!(matches!(
+            get_colorscheme(&dir.0, "unknown"),
+            Err(ColorschemeError::Parse { .. })
+        ));
+
+        // The names are only known while the colorscheme is read
+        let palette = HashMap::from([("base0B".to_string(), Color::rgb(1, 2, 3))]);
+        let color = color::with_palette(palette, || serde_yaml::from_str::<Color>("base0B"));
+        assert_eq!(color.unwrap(), Color::rgb(1, 2, 3));
+        assert!(serde_yaml::from_str::<Color>("base0B").is_err());
+    }
+}
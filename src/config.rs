@@ -0,0 +1,186 @@
+use crate::{
+    animation::Animation,
+    battery,
+    colorscheme::Colors,
+    render::{Fill, Mask},
+    text::Text,
+};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Contents of config.yaml, everything in it can be overridden from the command line
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name of the image in the images directory
+    pub name: Option<String>,
+    pub outputs: Vec<String>,
+    /// Seconds between battery checks when no power supply event arrives
+    pub interval: Option<u64>,
+    /// Capacity below which the low_battery color is used by colorschemes without thresholds
+    pub low_battery: u8,
+    pub power_supply: PathBuf,
+    /// Only show the battery with this name
+    pub battery: Option<String>,
+    pub backend: Backend,
+    pub layout: Layout,
+    pub animation: Animation,
+    /// Text drawn next to the indicator, nothing is drawn if not given
+    pub text: Option<Text>,
+    /// Colors to use instead of the colorscheme named after the image
+    pub colors: Option<Colors>,
+    /// Exit when the colorscheme can't be loaded on startup instead of using the default colors
+    pub strict: bool,
+    /// Settings of each image, by name
+    pub images: HashMap<String, ImageConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            name: None,
+            outputs: Vec::new(),
+            interval: None,
+            low_battery: 30,
+            power_supply: PathBuf::from(battery::POWER_SUPPLY),
+            battery: None,
+            backend: Backend::default(),
+            layout: Layout::default(),
+            animation: Animation::default(),
+            text: None,
+            colors: None,
+            strict: false,
+            images: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Backend {
+    #[default]
+    Wlrs,
+    File {
+        path: PathBuf,
+    },
+    Command {
+        exec: String,
+    },
+    X11,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    /// Draw one indicator per battery instead of combining them
+    pub per_pack: bool,
+    /// Render at this size instead of the size of each output
+    #[serde(deserialize_with = "deserialize_resolution")]
+    pub resolution: Option<(u32, u32)>,
+    /// Measure the battery against its design capacity and draw what it lost to wear in the
+    /// lost_capacity color
+    pub health: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageConfig {
+    pub fill: Fill,
+    pub mask: Mask,
+    /// Number of frames in a sheet.png, defaults to square frames
+    pub frames: Option<u32>,
+}
+
+impl Config {
+    /// Loads the config from `path`, a missing file is the same as an empty one
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = match fs::read_to_string(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("Failed to read {}: {err}", path.display()).into()),
+        };
+        if file.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let config: Self = serde_yaml::from_str(&file)
+            .map_err(|err| format!("Invalid config {}: {err}", path.display()))?;
+        config
+            .validate()
+            .map_err(|err| format!("Invalid config {}: {err}", path.display()))?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.low_battery > 100 {
+            return Err(format!(
+                "low_battery: {} is not a percentage between 0 and 100",
+                self.low_battery
+            ));
+        }
+        if self.interval == Some(0) {
+            return Err("interval: has to be at least 1 second".to_string());
+        }
+        if self.animation.fps == 0 {
+            return Err("animation.fps: has to be at least 1".to_string());
+        }
+        if matches!(&self.colors, Some(colors) if colors.palette.is_some()) {
+            return Err(
+                "colors.palette: palettes can only be used in colorschemes.yaml".to_string(),
+            );
+        }
+        if matches!(&self.text, Some(text) if text.size <= 0.0) {
+            return Err("text.size: has to be positive".to_string());
+        }
+        if let Some((name, _)) = self
+            .images
+            .iter()
+            .find(|(_, image)| image.mask.tolerance < 0.0)
+        {
+            return Err(format!("images.{name}.mask.tolerance: can't be negative"));
+        }
+        if let Some((name, _)) = self
+            .images
+            .iter()
+            .find(|(_, image)| image.frames == Some(0))
+        {
+            return Err(format!("images.{name}.frames: has to be at least 1"));
+        }
+        match &self.backend {
+            Backend::File { path } if path.as_os_str().is_empty() => {
+                Err("backend.path: can't be empty".to_string())
+            }
+            Backend::Command { exec } if exec.trim().is_empty() => {
+                Err("backend.exec: can't be empty".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn parse_resolution(resolution: &str) -> Result<(u32, u32), String> {
+    let (width, height) = resolution
+        .split_once('x')
+        .ok_or("Resolution should look like 1920x1080")?;
+    let parse = |size: &str| match size.parse::<u32>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("Invalid size {size:?} in {resolution}")),
+    };
+
+    Ok((parse(width)?, parse(height)?))
+}
+
+fn deserialize_resolution<'de, D>(deserializer: D) -> Result<Option<(u32, u32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|resolution| parse_resolution(&resolution).map_err(de::Error::custom))
+        .transpose()
+}
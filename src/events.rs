@@ -0,0 +1,308 @@
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::{
+    collections::HashMap,
+    fs, io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+pub enum Event {
+    /// A power supply changed its state, e.g. the charger was plugged in
+    PowerSupply,
+    /// Something in the config directory, its images or the config file changed
+    Reload,
+}
+
+pub trait EventSource: Send + 'static {
+    /// Blocks until the next event arrives
+    fn next(&mut self) -> io::Result<Event>;
+}
+
+/// Forwards events from `source` to `tx` on a background thread until either side goes away,
+/// `what` names the events when the source fails
+pub fn spawn(
+    mut source: impl EventSource,
+    tx: Sender<Event>,
+    what: &'static str,
+) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        match source.next() {
+            Ok(event) => {
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                eprintln!("Stopped listening for {what}: {err}");
+                break;
+            }
+        }
+    })
+}
+
+/// Listens to kernel uevents and reports the ones coming from the power_supply subsystem
+pub struct Uevent {
+    socket: OwnedFd,
+    buffer: [u8; 8192],
+}
+
+impl Uevent {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // Group 1 carries the events broadcast by the kernel itself
+        addr.nl_groups = 1;
+        let res = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            socket,
+            buffer: [0; 8192],
+        })
+    }
+}
+
+impl EventSource for Uevent {
+    fn next(&mut self) -> io::Result<Event> {
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    self.buffer.as_mut_ptr() as *mut libc::c_void,
+                    self.buffer.len(),
+                    0,
+                )
+            };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // A burst of events overflowed the socket and some were dropped, one of
+                    // them could have been from a power supply
+                    Some(libc::ENOBUFS) => return Ok(Event::PowerSupply),
+                    _ => return Err(err),
+                }
+            }
+
+            // Messages are NUL separated "KEY=value" pairs following an "action@devpath" header
+            if self.buffer[..len as usize]
+                .split(|byte| *byte == 0)
+                .any(|field| field == b"SUBSYSTEM=power_supply")
+            {
+                return Ok(Event::PowerSupply);
+            }
+        }
+    }
+}
+
+/// Watches the config directory, the images in it and the directory of the config file for
+/// changes
+pub struct Watcher {
+    inotify: Inotify,
+    watches: Watches,
+    /// Directories watched along with every directory below them
+    trees: Vec<PathBuf>,
+    /// Files that never cause a reload, like the wallpaper written by ruin itself
+    ignored: Vec<PathBuf>,
+    buffer: [u8; 1024],
+}
+
+impl Watcher {
+    /// Watches `dirs` themselves and `trees` all the way down
+    pub fn new(dirs: Vec<PathBuf>, trees: Vec<PathBuf>, ignored: &[&Path]) -> io::Result<Self> {
+        let inotify = Inotify::init()?;
+        let mut watches = Watches {
+            watches: inotify.watches(),
+            dirs: Arc::default(),
+        };
+        watch(&mut watches, &dirs, false)?;
+        watch(&mut watches, &trees, true)?;
+
+        Ok(Self {
+            inotify,
+            watches,
+            trees,
+            ignored: ignored.iter().map(|path| resolve(path)).collect(),
+            buffer: [0; 1024],
+        })
+    }
+
+    /// A handle to watch more directories with once the watcher runs on its own thread
+    pub fn watches(&self) -> Watches {
+        self.watches.clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct Watches {
+    watches: inotify::Watches,
+    /// The directory of every watch, events only name the file
+    dirs: Arc<Mutex<HashMap<WatchDescriptor, PathBuf>>>,
+}
+
+impl Watches {
+    pub fn add(&mut self, dir: &Path) -> io::Result<()> {
+        let descriptor = self.watches.add(dir, mask())?;
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        self.dirs.lock().unwrap().insert(descriptor, dir);
+        Ok(())
+    }
+}
+
+/// Files are picked up once they're written or moved in, not while they're still being written,
+/// deleting one counts too since it can change which image gets used
+fn mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+}
+
+/// Makes paths comparable to the ones of events, the file itself may not exist yet
+fn resolve(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => fs::canonicalize(match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        })
+        .map(|dir| dir.join(name))
+        .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Adds a watch for every directory in `paths` that exists, adding one twice is harmless so this
+/// is run again to pick up directories created since
+fn watch(watches: &mut Watches, paths: &[PathBuf], recursive: bool) -> io::Result<()> {
+    let mut dirs = paths
+        .iter()
+        .filter(|path| path.is_dir())
+        .cloned()
+        .collect::<Vec<_>>();
+    while let Some(dir) = dirs.pop() {
+        watches.add(&dir)?;
+        if let (true, Ok(entries)) = (recursive, fs::read_dir(&dir)) {
+            dirs.extend(
+                entries
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .filter(|path| path.is_dir()),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+impl EventSource for Watcher {
+    fn next(&mut self) -> io::Result<Event> {
+        loop {
+            let events = self.inotify.read_events_blocking(&mut self.buffer)?;
+            let dirs = self.watches.dirs.lock().unwrap();
+            // A created file is only worth reloading once it's written, a new or moved in
+            // directory is watched right away so the files put in it are noticed
+            let (changed, new_dir) = events
+                .filter(|event| {
+                    let path = event.name.zip(dirs.get(&event.wd));
+                    !path.is_some_and(|(name, dir)| self.ignored.contains(&dir.join(name)))
+                })
+                .fold((false, false), |(changed, new_dir), event| {
+                    let created = event.mask.contains(EventMask::CREATE);
+                    let moved = event.mask.contains(EventMask::MOVED_TO);
+                    let is_dir = event.mask.contains(EventMask::ISDIR);
+                    (
+                        changed || !created,
+                        new_dir || ((created || moved) && is_dir),
+                    )
+                });
+            drop(dirs);
+
+            // The new directory may be gone again already, the ones still there are watched
+            if new_dir {
+                if let Err(err) = watch(&mut self.watches, &self.trees, true) {
+                    eprintln!("Failed to watch new directories: {err}");
+                }
+            }
+            if changed {
+                return Ok(Event::Reload);
+            }
+        }
+    }
+}
+
+/// Replays events sent through a channel, useful for driving ruin without real hardware
+impl EventSource for Receiver<Event> {
+    fn next(&mut self) -> io::Result<Event> {
+        self.recv()
+            .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process, sync::mpsc, time::Duration};
+
+    #[test]
+    fn forwards_injected_events() {
+        let (inject, source) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let thread = spawn(source, tx, "injected events");
+
+        inject.send(Event::PowerSupply).unwrap();
+        inject.send(Event::Reload).unwrap();
+        let timeout = Duration::from_secs(1);
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Event::PowerSupply)));
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Event::Reload)));
+
+        // The source going away ends the thread, which closes the channel
+        drop(inject);
+        thread.join().unwrap();
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn watches_moved_in_directories() {
+        let dir = env::temp_dir().join(format!("ruin-test-{}-moved-dir", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("images")).unwrap();
+        fs::create_dir_all(dir.join("theme")).unwrap();
+        let watcher = Watcher::new(Vec::new(), vec![dir.join("images")], &[]).unwrap();
+        let (tx, rx) = mpsc::channel();
+        spawn(watcher, tx, "file changes");
+
+        let timeout = Duration::from_secs(5);
+        fs::rename(dir.join("theme"), dir.join("images/theme")).unwrap();
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Event::Reload)));
+        // Files written into the directory are only noticed if it got watched
+        fs::write(dir.join("images/theme/50.png"), "").unwrap();
+        let reload = rx.recv_timeout(timeout);
+        let _ = fs::remove_dir_all(&dir);
+        assert!(matches!(reload, Ok(Event::Reload)));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::render::Mask;
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use std::{error::Error, fs, path::Path};
+
+/// What decides whether a pixel becomes part of the mask
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Select {
+    /// Pixels at least as opaque as the threshold
+    #[default]
+    Alpha,
+    /// Pixels at least as bright as the threshold
+    Luminance,
+    /// Pixels within the threshold (as distance in RGB) of a color
+    Color,
+}
+
+pub struct Import {
+    pub select: Select,
+    pub threshold: f32,
+    /// Selects the pixels that would otherwise be left out
+    pub invert: bool,
+    /// Source color for [`Select::Color`]
+    pub color: [u8; 3],
+}
+
+impl Import {
+    /// Paints the selected pixels in the mask color, everything else is kept as is
+    pub fn convert(&self, image: &mut RgbaImage) {
+        let mask = Mask::default().color.channels();
+        image.pixels_mut().for_each(|pixel| {
+            // Transparent pixels are never part of the mask, even when inverting
+            let Rgba([r, g, b, a]) = *pixel;
+            if a == 0 {
+                return;
+            }
+
+            let selected = match self.select {
+                Select::Alpha => a as f32 >= self.threshold,
+                Select::Luminance => {
+                    0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32 >= self.threshold
+                }
+                Select::Color => {
+                    [r, g, b]
+                        .iter()
+                        .zip(self.color)
+                        .map(|(channel, color)| (*channel as f32 - color as f32).powi(2))
+                        .sum::<f32>()
+                        .sqrt()
+                        <= self.threshold
+                }
+            };
+            if selected != self.invert {
+                *pixel = Rgba([mask[0], mask[1], mask[2], 255]);
+            }
+        });
+    }
+
+    /// Converts the image at `file` and saves it as images/<name>.png
+    pub fn install(
+        &self,
+        file: &Path,
+        ruin_dir: &Path,
+        name: &str,
+        force: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        // The name is used as is in the path, it mustn't lead out of the images directory
+        if name.is_empty() || name.contains("..") || name.contains(['/', '\\']) {
+            return Err(
+                format!("{name:?} can't be used as a name, it has to be a file name").into(),
+            );
+        }
+        let mut image = image::open(file)
+            .map_err(|err| format!("Failed to open {}: {err}", file.display()))?
+            .to_rgba8();
+        self.convert(&mut image);
+
+        let images_dir = ruin_dir.join("images");
+        fs::create_dir_all(&images_dir)?;
+        let path = images_dir.join(format!("{name}.png"));
+        if path.exists() && !force {
+            return Err(format!(
+                "{} already exists, use --force to replace it",
+                path.display()
+            )
+            .into());
+        }
+        image.save(&path)?;
+        println!("Installed {}, use it with `ruin {name}`", path.display());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_names_outside_images() {
+        let import = Import {
+            select: Select::Alpha,
+            threshold: 128.0,
+            invert: false,
+            color: [0; 3],
+        };
+        let ruin_dir = Path::new("/nonexistent");
+        ["../../x", "a/b", "..", "..x", "", "a\\b"]
+            .iter()
+            .for_each(|name| {
+                let err = import
+                    .install(Path::new("missing.png"), ruin_dir, name, false)
+                    .unwrap_err();
+                assert!(
+                    err.to_string().contains("can't be used as a name"),
+                    "{name}: {err}"
+                );
+            });
+    }
+}
@@ -1,156 +1,689 @@
+mod animation;
+mod backend;
 mod battery;
+mod color;
+mod colorscheme;
+mod config;
+mod events;
+mod import;
+mod render;
+mod text;
 
-use battery::{find_battery_path, Battery, BatteryStatus};
-use clap::Parser;
-use image::{imageops, DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgba, RgbaImage};
-use inotify::{Inotify, WatchMask};
-use serde::{Deserialize, Serialize};
+use animation::Animated;
+use backend::{Backend, BackendKind, Output};
+use battery::{find_battery_paths, Battery, BatteryStatus, RateHistory};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use color::Color;
+use colorscheme::{get_colorscheme, palette_path, Colors, ColorschemeError};
+use config::{parse_resolution, Config};
+use events::{Event, Uevent, Watcher, Watches};
+use import::{Import, Select};
+use render::{create, Canvas, Indicator};
 use std::{
-    collections::HashMap,
     error::Error,
-    fs,
-    num::NonZeroU32,
+    io,
     path::{Path, PathBuf},
-    sync::{mpsc, Arc, RwLock},
+    process,
+    sync::mpsc::{self, RecvTimeoutError},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use wlrs::{CropMode, Image, SetType, Wlrs};
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Colors {
-    charging: [u8; 3],
-    default: [u8; 3],
-    low_battery: [u8; 3],
-    background: [u8; 3],
-}
-
-impl Default for Colors {
-    fn default() -> Self {
-        Self {
-            charging: [255, 255, 0],
-            default: [91, 194, 54],
-            low_battery: [191, 19, 28],
-            background: [40, 40, 40],
-        }
-    }
-}
 
 #[derive(Parser, Debug)]
 struct Args {
-    name: String,
+    /// Name of the image, defaults to the one in config.yaml
+    name: Option<String>,
+    /// Config file to use instead of ~/.config/ruin/config.yaml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Exit when the colorscheme can't be loaded instead of using the default colors
+    #[arg(long, global = true)]
+    strict: bool,
     #[arg(short, long, num_args(0..))]
     outputs: Vec<String>,
-    #[arg(short, long, num_args(0..))]
+    /// Seconds between battery checks when no power supply event arrives
+    #[arg(short, long, num_args(0..), value_parser = clap::value_parser!(u64).range(1..))]
     time: Option<u64>,
+    /// How to set the wallpaper
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
+    /// File to write the wallpaper to with the file backend
+    #[arg(long, required_if_eq("backend", "file"))]
+    file: Option<PathBuf>,
+    /// Command to run with the command backend, {file} is replaced with the rendered image
+    #[arg(long, required_if_eq("backend", "command"))]
+    exec: Option<String>,
+    /// Render at this size (e.g. 1920x1080) instead of the size of each output
+    #[arg(short, long, value_parser = parse_resolution)]
+    resolution: Option<(u32, u32)>,
+    #[command(flatten)]
+    batteries: BatteryArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Whether any of the arguments only setting the wallpaper uses were given
+    fn wallpaper_args(&self) -> bool {
+        self.name.is_some()
+            || !self.outputs.is_empty()
+            || self.time.is_some()
+            || self.backend.is_some()
+            || self.file.is_some()
+            || self.exec.is_some()
+            || self.resolution.is_some()
+    }
+}
+
+/// Used by every mode, so they can go before or after a subcommand
+#[derive(clap::Args, Debug)]
+struct BatteryArgs {
+    /// Only show the battery with this name (e.g. BAT1)
+    #[arg(short, long, global = true)]
+    battery: Option<String>,
+    /// Draw one indicator per battery instead of combining them
+    #[arg(short, long, global = true)]
+    per_pack: bool,
+    /// Directory to look for batteries in [default: /sys/class/power_supply]
+    #[arg(long, global = true, env = "RUIN_POWER_SUPPLY")]
+    power_supply: Option<PathBuf>,
+}
+
+impl BatteryArgs {
+    fn apply(&self, config: &mut Config) {
+        if let Some(battery) = &self.battery {
+            config.battery = Some(battery.clone());
+        }
+        if let Some(power_supply) = &self.power_supply {
+            config.power_supply = power_supply.clone();
+        }
+        config.layout.per_pack |= self.per_pack;
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render the wallpaper to a file instead of setting it
+    Render {
+        name: String,
+        /// Battery level to render, the real one is used if not given
+        #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        capacity: Option<u8>,
+        /// Battery status to render, the real one is used if not given
+        #[arg(short, long)]
+        status: Option<BatteryStatus>,
+        /// Battery health to render, in percent of the design capacity
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        health: Option<u8>,
+        /// Where to write the image, the format is picked from the extension (png, jpg, webp, ...)
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Size of the rendered image (e.g. 1920x1080)
+        #[arg(short, long, value_parser = parse_resolution, default_value = "3840x2160")]
+        resolution: (u32, u32),
+    },
+    /// Print the battery level and how long it lasts
+    Status,
+    /// Turn an image into one ruin can fill and install it into the images directory
+    Import {
+        file: PathBuf,
+        /// Name to install the image as, defaults to the file name
+        #[arg(short, long)]
+        name: Option<String>,
+        /// What decides which pixels get filled
+        #[arg(short, long, value_enum, default_value_t = Select::Alpha)]
+        select: Select,
+        /// Opacity or brightness (0-255) from which on pixels get filled, or how far off (as
+        /// distance in RGB) they may be from --color [default: 128, or 0 for color]
+        #[arg(short, long)]
+        threshold: Option<f32>,
+        /// Fill the pixels that wouldn't be filled otherwise
+        #[arg(short, long)]
+        invert: bool,
+        /// Color of the pixels to fill when selecting by color (e.g. #ff0000)
+        #[arg(short, long, required_if_eq("select", "color"))]
+        color: Option<Color>,
+        /// Replace an image with the same name
+        #[arg(short, long)]
+        force: bool,
+    },
 }
 
 fn main() {
     let args = Args::parse();
+    if args.command.is_some() && args.wallpaper_args() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the image name, -o, -t, -r, --backend, --file and --exec only apply to setting \
+                 the wallpaper, they can't be used with a subcommand",
+            )
+            .exit();
+    }
 
     let ruin_dir = {
         let home_dir = dirs::config_dir().expect("XDG_HOME_CONFIG not found");
         PathBuf::from(format!("{}/ruin", home_dir.display()))
     };
 
-    let img_path = ruin_dir.join(format!("images/{}.png", args.name));
-    let image =
-        image::open(img_path).unwrap_or_else(|_| panic!("Image {}.png not found", args.name));
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(|| ruin_dir.join("config.yaml"));
+    let mut config = Config::load(&config_path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    config.strict |= args.strict;
+    args.batteries.apply(&mut config);
+
+    match args.command {
+        Some(Command::Render {
+            name,
+            capacity,
+            status,
+            health,
+            output,
+            resolution: (width, height),
+        }) => {
+            let indicator = Indicator::load(&ruin_dir, &name, &config).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                process::exit(1);
+            });
+            let color_scheme = startup_colors(&ruin_dir, &name, &config);
+
+            let mut batteries = match (capacity, status) {
+                (Some(capacity), status) => vec![Battery {
+                    status: status.unwrap_or(BatteryStatus::Discharging),
+                    capacity: capacity as f32,
+                    energy: None,
+                    rate: None,
+                    time_to_empty: None,
+                    time_to_full: None,
+                    health: None,
+                    cycle_count: None,
+                }],
+                (None, status) => {
+                    let mut batteries = read_batteries(&find_batteries(&config), &config)
+                        .unwrap_or_else(|err| panic!("Failed to read battery: {err}"));
+                    if let Some(status) = status {
+                        batteries
+                            .iter_mut()
+                            .for_each(|battery| battery.status = status.clone());
+                    }
+                    batteries
+                }
+            };
+            if let Some(health) = health {
+                config.layout.health = true;
+                batteries
+                    .iter_mut()
+                    .for_each(|battery| battery.health = Some(health as f32));
+            }
+
+            let wallpaper = (
+                Output {
+                    width,
+                    height,
+                    ..Default::default()
+                },
+                create(
+                    &batteries,
+                    &color_scheme,
+                    &config,
+                    &indicator,
+                    width,
+                    height,
+                ),
+            );
+            backend::File::new(output.clone())
+                .set(&[wallpaper])
+                .unwrap_or_else(|err| panic!("Failed to write {}: {err}", output.display()));
+        }
+        Some(Command::Status) => {
+            read_batteries(&find_batteries(&config), &config)
+                .unwrap_or_else(|err| panic!("Failed to read battery: {err}"))
+                .iter()
+                .for_each(|battery| println!("{battery}"));
+        }
+        Some(Command::Import {
+            file,
+            name,
+            select,
+            threshold,
+            invert,
+            color,
+            force,
+        }) => {
+            let Some(name) = name.or_else(|| {
+                file.file_stem()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }) else {
+                eprintln!(
+                    "Can't tell the name of {}, pass one with --name",
+                    file.display()
+                );
+                process::exit(1);
+            };
+            let import = Import {
+                select,
+                threshold: threshold.unwrap_or(match select {
+                    Select::Color => 0.0,
+                    _ => 128.0,
+                }),
+                invert,
+                color: color.map(Color::channels).unwrap_or_default(),
+            };
+            if let Err(err) = import.install(&file, &ruin_dir, &name, force) {
+                eprintln!("{err}");
+                process::exit(1);
+            }
+        }
+        None => {
+            // Applied again whenever the config is reloaded, the command line keeps winning
+            let apply_args = move |config: &mut Config| {
+                if let Some(name) = &args.name {
+                    config.name = Some(name.clone());
+                }
+                if !args.outputs.is_empty() {
+                    config.outputs = args.outputs.clone();
+                }
+                if let Some(time) = args.time {
+                    config.interval = Some(time);
+                }
+                if let Some(backend) = args.backend {
+                    config.backend = match backend {
+                        BackendKind::Wlrs => config::Backend::Wlrs,
+                        BackendKind::File => config::Backend::File {
+                            path: args.file.clone().expect("--file is required"),
+                        },
+                        BackendKind::Command => config::Backend::Command {
+                            exec: args.exec.clone().expect("--exec is required"),
+                        },
+                        BackendKind::X11 => config::Backend::X11,
+                    };
+                }
+                if args.resolution.is_some() {
+                    config.layout.resolution = args.resolution;
+                }
+                args.batteries.apply(config);
+                config.strict |= args.strict;
+            };
+            apply_args(&mut config);
+
+            run(config, ruin_dir, config_path, apply_args)
+        }
+    }
+}
 
-    let mut previous = Battery {
-        capacity: 0,
-        status: BatteryStatus::NotCharging,
+fn run(
+    mut config: Config,
+    ruin_dir: PathBuf,
+    config_path: PathBuf,
+    apply_args: impl Fn(&mut Config),
+) {
+    let Some(name) = config.name.clone() else {
+        eprintln!("{NO_NAME}");
+        process::exit(1);
     };
+    let mut indicator = Indicator::load(&ruin_dir, &name, &config).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+
+    let mut previous = Vec::new();
+    let mut histories: Vec<RateHistory> = Vec::new();
+    let mut canvases: Vec<Option<Canvas>> = Vec::new();
 
-    let color_scheme = Arc::new(RwLock::new(
-        get_colorscheme(&ruin_dir, &args.name).unwrap_or_default(),
-    ));
-    let battery_path = find_battery_path().expect("Battery not found");
+    let mut color_scheme = startup_colors(&ruin_dir, &name, &config);
+    let battery_paths = find_batteries(&config);
 
     let (tx, rx) = mpsc::channel();
 
-    {
-        let color_scheme = Arc::clone(&color_scheme);
-        thread::spawn(move || {
-            let mut inotify = Inotify::init().unwrap();
-            _ = inotify.watches().add(
-                &ruin_dir,
-                WatchMask::MODIFY | WatchMask::MOVED_TO | WatchMask::CREATE,
-            );
-            let mut buffer = [0; 1024];
-            loop {
-                if let Ok(events) = inotify.read_events_blocking(&mut buffer) {
-                    events.for_each(|_| {
-                        *color_scheme.write().unwrap() =
-                            get_colorscheme(&ruin_dir, &args.name).unwrap_or_default();
-                        _ = tx.send(());
-                    });
+    let uevents = match Uevent::new() {
+        Ok(uevent) => Some(events::spawn(uevent, tx.clone(), "power supply events")),
+        Err(err) => {
+            eprintln!("Failed to subscribe to power supply events: {err}");
+            None
+        }
+    };
+
+    let mut backend: Box<dyn Backend> = match &config.backend {
+        config::Backend::Wlrs => Box::new(backend::Wlrs::new(config.outputs.clone()).unwrap()),
+        config::Backend::File { path } => Box::new(backend::File::new(path.clone())),
+        config::Backend::Command { exec } => Box::new(backend::Command::new(exec.clone())),
+        config::Backend::X11 => Box::new(backend::X11::new().unwrap()),
+    };
+
+    // The config file can live outside of the config directory, its directory isn't watched
+    // recursively since that could be all of ~/.config. The wallpaper may be written to one of
+    // the watched directories, that would reload over and over
+    let config_dir = config_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut watches = match Watcher::new(
+        vec![ruin_dir.clone(), config_dir.to_path_buf()],
+        vec![ruin_dir.join("images")],
+        backend.file().as_slice(),
+    ) {
+        Ok(watcher) => {
+            let watches = watcher.watches();
+            events::spawn(watcher, tx, "config changes");
+            Some(watches)
+        }
+        Err(err) => {
+            eprintln!("Failed to watch {}: {err}", ruin_dir.display());
+            None
+        }
+    };
+    watch_palette(&mut watches, &ruin_dir, &config);
+
+    warn_animation(&config, &*backend);
+    let mut animated: Option<(Animated, Instant)> = None;
+    let mut reload = false;
+    loop {
+        let batteries = read_batteries(&battery_paths, &config).map(|mut batteries| {
+            histories.resize_with(batteries.len(), RateHistory::default);
+            batteries
+                .iter_mut()
+                .zip(&mut histories)
+                .for_each(|(battery, history)| history.update(battery));
+            batteries
+        });
+        match batteries {
+            Ok(batteries) if batteries != previous || reload => {
+                let outputs = backend.outputs().unwrap_or_else(|err| {
+                    eprintln!("Failed to query outputs: {err}");
+                    vec![Output::default()]
+                });
+                // Canvases are drawn from scratch only when something else than the battery
+                // changed, otherwise just the difference to the last level is drawn
+                if reload {
+                    canvases.clear();
                 }
+                canvases.resize_with(outputs.len(), || None);
+                let canvases = outputs
+                    .into_iter()
+                    .zip(&mut canvases)
+                    .map(|(output, canvas)| {
+                        let (width, height) = config
+                            .layout
+                            .resolution
+                            .unwrap_or((output.width, output.height));
+                        if !canvas
+                            .as_ref()
+                            .is_some_and(|canvas| canvas.fits(width, height, batteries.len()))
+                        {
+                            *canvas = None;
+                        }
+                        let canvas = canvas.get_or_insert_with(|| {
+                            Canvas::new(&indicator, &color_scheme, batteries.len(), width, height)
+                        });
+                        canvas.update(&batteries, &color_scheme, &config, &indicator);
+                        (output, &*canvas)
+                    })
+                    .collect::<Vec<_>>();
+
+                // Animations only run while charging, on battery the wallpaper stays still
+                let charging = batteries
+                    .iter()
+                    .any(|battery| battery.status == BatteryStatus::Charging);
+                let effect = config
+                    .animation
+                    .effect
+                    .filter(|_| charging && backend.animates());
+                // Frame themes have no fill to draw the effect over, and neither has an empty
+                // battery
+                let fills = effect
+                    .map(|_| {
+                        canvases
+                            .iter()
+                            .map(|(_, canvas)| canvas.filled())
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|fills| fills.iter().any(|fill| !fill.is_empty()));
+                animated = match effect.zip(fills) {
+                    Some((effect, fills)) => {
+                        let wallpapers = canvases
+                            .into_iter()
+                            .zip(fills)
+                            .map(|((output, canvas), fill)| (output, canvas.image().clone(), fill))
+                            .collect();
+                        Some((Animated::new(effect, wallpapers), Instant::now()))
+                    }
+                    None => {
+                        let wallpapers = canvases
+                            .into_iter()
+                            .map(|(output, canvas)| (output, canvas.image().clone()))
+                            .collect::<Vec<_>>();
+                        if let Err(err) = backend.set(&wallpapers) {
+                            eprintln!("Failed to set wallpaper: {err}");
+                        }
+                        None
+                    }
+                };
+                previous = batteries;
             }
+            Ok(_) => {}
+            Err(err) => eprintln!("Failed to read battery: {err}"),
+        }
+
+        reload = false;
+        // Without uevents the timer is all we have, so it has to tick a lot more often
+        let listening = uevents.as_ref().is_some_and(|thread| !thread.is_finished());
+        let interval = config.interval.unwrap_or(match listening {
+            true => 60,
+            false => 5,
         });
+        let frame_time = Duration::from_secs_f32(1.0 / config.animation.fps as f32);
+        // Animation frames are drawn until something happens or the battery is due a check
+        let deadline = Instant::now() + Duration::from_secs(interval);
+        let event = loop {
+            let Some((animation, start)) = &mut animated else {
+                break rx.recv_timeout(Duration::from_secs(interval));
+            };
+            if let Err(err) = backend.set(animation.frame(start.elapsed().as_secs_f32())) {
+                eprintln!("Failed to set wallpaper: {err}");
+            }
+            match rx.recv_timeout(frame_time) {
+                Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => continue,
+                event => break event,
+            }
+        };
+        match event {
+            // Everything is loaded before any of it is used, so a broken file leaves the
+            // wallpaper as it was
+            Ok(Event::Reload) => {
+                // Themes are often switched by rewriting a bunch of files at once, they're all
+                // taken in with a single reload
+                while rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+                match reload_config(&ruin_dir, &config_path, &apply_args) {
+                    Ok(reloaded) => {
+                        (config, indicator, color_scheme) = reloaded;
+                        warn_animation(&config, &*backend);
+                        reload = true;
+                    }
+                    Err(err) => eprintln!("Failed to reload, keeping the previous setup: {err}"),
+                }
+                watch_palette(&mut watches, &ruin_dir, &config);
+            }
+            Ok(Event::PowerSupply) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_secs(interval)),
+        }
     }
+}
 
-    let wlrs = Wlrs::new().unwrap();
-    loop {
-        let battery = Battery::new(&battery_path);
-        if battery != previous || rx.try_recv().is_ok() {
-            let image = create(&battery, &color_scheme.read().unwrap(), &image);
-            let image_data = Image::new(
-                &image,
-                NonZeroU32::new(image.width()).unwrap(),
-                NonZeroU32::new(image.height()).unwrap(),
-            )
-            .unwrap();
-            _ = wlrs.set(SetType::Img(image_data), &args.outputs, CropMode::Fit(None));
-            previous = battery;
-        }
-        thread::sleep(Duration::from_secs(args.time.unwrap_or(5)));
+fn warn_animation(config: &Config, backend: &dyn Backend) {
+    if config.animation.effect.is_some() && !backend.animates() {
+        eprintln!("The file and command backends aren't animated, animation.effect is ignored");
     }
 }
 
-fn get_colorscheme(path: &Path, name: &String) -> Result<Colors, Box<dyn Error>> {
-    let file = fs::read_to_string(path.join("colorschemes.yaml"))?;
-    let mut colorschemes: HashMap<String, Colors> = serde_yaml::from_str(&file)?;
-    Ok(colorschemes.remove(name).ok_or("")?)
+/// Palettes usually live outside of the config directory, like the one pywal writes to
+/// ~/.cache/wal, so their directory is watched too
+fn watch_palette(watches: &mut Option<Watches>, ruin_dir: &Path, config: &Config) {
+    let (Some(watches), None, Some(name)) = (watches, &config.colors, &config.name) else {
+        return;
+    };
+    let Some(dir) = palette_path(ruin_dir, name)
+        .and_then(|palette| palette.parent().map(Path::to_path_buf))
+        .filter(|dir| dir.is_dir())
+    else {
+        return;
+    };
+    if let Err(err) = watches.add(&dir) {
+        eprintln!("Failed to watch {}: {err}", dir.display());
+    }
 }
 
-fn create(battery: &Battery, color_scheme: &Colors, image: &DynamicImage) -> RgbaImage {
-    let (status, capacity) = (&battery.status, battery.capacity);
-    let (width, height) = (image.width(), image.height());
+const NO_NAME: &str = "No image given, pass its name or set name in config.yaml";
 
-    let color = match status {
-        BatteryStatus::Charging => color_scheme.charging,
-        _ if capacity >= 30_u8 => color_scheme.default,
-        _ => color_scheme.low_battery,
-    };
+/// Loads the config file again along with the image and colors it names. The backend, outputs
+/// and batteries stay the ones ruin was started with
+fn reload_config(
+    ruin_dir: &Path,
+    config_path: &Path,
+    apply_args: impl Fn(&mut Config),
+) -> Result<(Config, Indicator, Colors), Box<dyn Error>> {
+    let mut config = Config::load(config_path)?;
+    apply_args(&mut config);
+    let name = config.name.clone().ok_or(NO_NAME)?;
+    let indicator = Indicator::load(ruin_dir, &name, &config)?;
+    let color_scheme = reload_colors(ruin_dir, &name, &config)?;
 
-    let color = [color[0], color[1], color[2], 255];
-    let bg = [
-        color_scheme.background[0],
-        color_scheme.background[1],
-        color_scheme.background[2],
-        255,
-    ];
-
-    let mut output = RgbaImage::new(width, height);
-    let capacity = 1.0 - capacity as f32 / 100.0;
-    image.pixels().for_each(|(x, y, pixel)| match pixel {
-        Rgba([143, 188, 187, 255]) if y as f32 > height as f32 * capacity => {
-            output.put_pixel(x, y, Rgba(color))
+    Ok((config, indicator, color_scheme))
+}
+
+/// Like on startup an image without a colorscheme gets the default colors, unless in strict
+/// mode. A colorscheme that can't be read or parsed keeps the previous colors instead
+fn reload_colors(ruin_dir: &Path, name: &str, config: &Config) -> Result<Colors, ColorschemeError> {
+    match get_colors(ruin_dir, name, config) {
+        Err(err @ ColorschemeError::Missing { .. }) if !config.strict => {
+            eprintln!("{err}, using the default colors");
+            Ok(Colors::default())
         }
-        Rgba([_, _, _, alpha]) if alpha < 255 => output.put_pixel(x, y, Rgba(bg)),
-        _ => output.put_pixel(x, y, pixel.to_rgba()),
-    });
-    let mut background = ImageBuffer::new(3840, 2160);
-    background
-        .pixels_mut()
-        .collect::<Vec<_>>()
-        .iter_mut()
-        .for_each(|pixel| **pixel = Rgba(bg));
-
-    let x = (3840 - width) / 2;
-    let y = (2160 - height) / 2;
-    imageops::overlay(&mut background, &output, x as i64, y as i64);
-
-    background
+        colors => colors,
+    }
+}
+
+/// Colors from config.yaml win over the colorscheme named after the image, without either the
+/// default colors are used
+fn get_colors(ruin_dir: &Path, name: &str, config: &Config) -> Result<Colors, ColorschemeError> {
+    match &config.colors {
+        Some(colors) => Ok(colors.clone()),
+        None => Ok(get_colorscheme(ruin_dir, name)?.unwrap_or_default()),
+    }
+}
+
+/// A broken colorscheme only stops ruin from starting in strict mode, otherwise it's reported
+/// and the default colors are used
+fn startup_colors(ruin_dir: &Path, name: &str, config: &Config) -> Colors {
+    get_colors(ruin_dir, name, config).unwrap_or_else(|err| match config.strict {
+        true => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+        false => {
+            eprintln!("{err}, using the default colors");
+            Colors::default()
+        }
+    })
+}
+
+fn find_batteries(config: &Config) -> Vec<PathBuf> {
+    let battery_paths = find_battery_paths(&config.power_supply)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {err}", config.power_supply.display()))
+        .into_iter()
+        .filter(|path| match &config.battery {
+            Some(name) => path.file_name() == Some(name.as_ref()),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    if battery_paths.is_empty() {
+        panic!("Battery not found");
+    }
+
+    battery_paths
+}
+
+fn read_batteries(battery_paths: &[PathBuf], config: &Config) -> io::Result<Vec<Battery>> {
+    match config.layout.per_pack {
+        true => battery_paths
+            .iter()
+            .map(|path| Battery::new(path))
+            .collect(),
+        false => Battery::combined(battery_paths).map(|battery| vec![battery]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn verify_args() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn global_args_go_before_subcommands() {
+        let args = Args::try_parse_from([
+            "ruin", "--config", "x", "render", "example", "-o", "out.png",
+        ])
+        .unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("x")));
+        assert!(!args.wallpaper_args());
+        assert!(matches!(args.command, Some(Command::Render { name, .. }) if name == "example"));
+
+        let args = Args::try_parse_from([
+            "ruin",
+            "--strict",
+            "-b",
+            "BAT1",
+            "status",
+            "--power-supply",
+            "/tmp/ps",
+        ])
+        .unwrap();
+        assert!(args.strict);
+        assert_eq!(args.batteries.battery.as_deref(), Some("BAT1"));
+        assert_eq!(args.batteries.power_supply, Some(PathBuf::from("/tmp/ps")));
+        assert!(matches!(args.command, Some(Command::Status)));
+    }
+
+    #[test]
+    fn name_without_subcommand() {
+        let args = Args::try_parse_from(["ruin", "--strict", "example", "-t", "30"]).unwrap();
+        assert_eq!(args.name.as_deref(), Some("example"));
+        assert_eq!(args.time, Some(30));
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn reload_without_colorscheme_uses_default_colors() {
+        let dir = env::temp_dir().join(format!("ruin-test-{}-reload", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("images")).unwrap();
+        image::RgbaImage::new(4, 4)
+            .save(dir.join("images/example.png"))
+            .unwrap();
+        fs::write(dir.join("config.yaml"), "name: example").unwrap();
+        fs::write(dir.join("colorschemes.yaml"), "other:\n  default: red").unwrap();
+        let reload = |strict| {
+            reload_config(&dir, &dir.join("config.yaml"), |config| {
+                config.strict = strict
+            })
+        };
+
+        let (_, _, colors) = reload(false).unwrap();
+        assert_eq!(colors.default, Colors::default().default);
+        assert!(reload(true).is_err());
+        // Broken colorschemes still keep the previous colors
+        fs::write(dir.join("colorschemes.yaml"), "example:\n  default: [1, 2").unwrap();
+        let broken = reload(false);
+        let _ = fs::remove_dir_all(&dir);
+        assert!(broken.is_err());
+    }
 }
@@ -0,0 +1,1038 @@
+use crate::{
+    battery::{Battery, BatteryStatus},
+    color::Color,
+    colorscheme::Colors,
+    config::Config,
+    text::{self, Label, Position},
+};
+use image::{
+    imageops, DynamicImage, GenericImageView, GrayAlphaImage, ImageBuffer, Luma, Rgba, RgbaImage,
+};
+use serde::Deserialize;
+use std::{error::Error, fs, ops::Range, path::Path};
+
+/// Which way the mask fills up as the capacity rises
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fill {
+    pub direction: Direction,
+    /// Angle the arc starts at, in degrees clockwise from the top
+    pub start: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    #[default]
+    BottomUp,
+    TopDown,
+    LeftToRight,
+    RightToLeft,
+    /// From the centre out
+    Radial,
+    /// Clockwise sweep around the centre, for circular gauges
+    Arc,
+}
+
+/// Which pixels of the image make up the part that gets filled
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mask {
+    pub color: Color,
+    /// How far off (as distance in RGB) a pixel may be from the mask color and still count
+    pub tolerance: f32,
+    pub intensity: Intensity,
+}
+
+impl Default for Mask {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(143, 188, 187),
+            tolerance: 0.0,
+            intensity: Intensity::default(),
+        }
+    }
+}
+
+/// How strongly a mask pixel takes on the fill color
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Intensity {
+    /// Only opaque pixels belong to the mask and are fully filled
+    #[default]
+    None,
+    /// Translucent pixels belong to the mask too and are filled as much as they are opaque,
+    /// keeping anti-aliased edges smooth
+    Alpha,
+    /// Pixels darker than the mask color are filled less, keeping the shading of the image
+    Luminance,
+}
+
+impl Mask {
+    /// Returns how strongly the pixel is filled, or None if it isn't part of the mask
+    fn weight(&self, pixel: &Rgba<u8>) -> Option<f32> {
+        let [r, g, b, a] = pixel.0;
+        let distance = [r, g, b]
+            .iter()
+            .zip(self.color.channels())
+            .map(|(channel, mask)| (*channel as f32 - mask as f32).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        if distance > self.tolerance {
+            return None;
+        }
+
+        let luminance =
+            |[r, g, b]: [u8; 3]| 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+        match self.intensity {
+            Intensity::None => (a == 255).then_some(1.0),
+            Intensity::Alpha => (a > 0).then_some(a as f32 / 255.0),
+            Intensity::Luminance if a == 255 => match luminance(self.color.channels()) {
+                mask if mask > 0.0 => Some((luminance([r, g, b]) / mask).min(1.0)),
+                _ => Some(1.0),
+            },
+            Intensity::Luminance => None,
+        }
+    }
+}
+
+/// An image together with everything needed to draw it
+pub struct Indicator {
+    source: Source,
+    label: Option<Label>,
+}
+
+/// What an indicator is drawn from
+enum Source {
+    /// An image with the part to fill picked out, by the mask color or by a separate mask
+    Mask(Classified),
+    /// Hand drawn frames for different levels, shown as they are instead of being filled
+    Frames(Frames),
+}
+
+/// The pixels of an image sorted out once when it's loaded, so drawing it never has to look at
+/// the mask again
+struct Classified {
+    /// How the image looks with nothing filled, transparent where the background shows through
+    empty: RgbaImage,
+    /// How strongly each pixel is filled, 0 outside of the mask
+    weights: ImageBuffer<Luma<f32>, Vec<f32>>,
+    /// Fill on top of the artwork instead of the background, for images with a separate mask
+    tint: bool,
+    progress: Progress,
+}
+
+/// Says how far along the fill a point of the image is, from 0 to 1, it's filled once the
+/// capacity is past that point
+#[derive(Clone, Copy)]
+struct Progress {
+    direction: Direction,
+    start: f32,
+    width: f32,
+    height: f32,
+    /// Distances from the centre the radial fill goes between
+    inner: f32,
+    outer: f32,
+}
+
+impl Progress {
+    fn new(fill: Fill, weights: &ImageBuffer<Luma<f32>, Vec<f32>>) -> Self {
+        let (width, height) = (weights.width() as f32, weights.height() as f32);
+        let (cx, cy) = (width / 2.0, height / 2.0);
+
+        // Radial fills are stretched over the part of the image the mask covers, so rings fill
+        // up from their inner edge rather than from the empty centre
+        let (inner, outer) = match fill.direction {
+            Direction::Radial => weights
+                .enumerate_pixels()
+                .filter(|(_, _, weight)| weight.0[0] > 0.0)
+                .map(|(x, y, _)| (x as f32 + 0.5 - cx).hypot(y as f32 + 0.5 - cy))
+                .fold((f32::MAX, 0.0_f32), |(inner, outer), distance| {
+                    (inner.min(distance), outer.max(distance))
+                }),
+            _ => (0.0, 0.0),
+        };
+
+        Self {
+            direction: fill.direction,
+            start: fill.start,
+            width,
+            height,
+            inner,
+            outer,
+        }
+    }
+
+    /// How far along the fill a point is, kept below 1 so a full battery fills every pixel
+    fn at(&self, x: f32, y: f32) -> f32 {
+        let (width, height) = (self.width, self.height);
+        let (cx, cy) = (width / 2.0, height / 2.0);
+        let progress = match self.direction {
+            Direction::BottomUp => (height - y) / height,
+            Direction::TopDown => y / height,
+            Direction::LeftToRight => x / width,
+            Direction::RightToLeft => (width - x) / width,
+            Direction::Radial => match self.outer > self.inner {
+                true => ((x - cx).hypot(y - cy) - self.inner) / (self.outer - self.inner),
+                false => 0.0,
+            },
+            Direction::Arc => {
+                let angle = (x - cx).atan2(cy - y).to_degrees();
+                (angle - self.start).rem_euclid(360.0) / 360.0
+            }
+        };
+        progress.clamp(0.0, 1.0 - f32::EPSILON)
+    }
+}
+
+impl Classified {
+    fn new(
+        image: DynamicImage,
+        mask_image: Option<GrayAlphaImage>,
+        fill: Fill,
+        mask: Mask,
+    ) -> Self {
+        let tint = mask_image.is_some();
+        let mut empty = image.to_rgba8();
+        let weights = ImageBuffer::from_fn(empty.width(), empty.height(), |x, y| {
+            let weight = match &mask_image {
+                Some(mask_image) => {
+                    let [luma, alpha] = mask_image.get_pixel(x, y).0;
+                    Some(luma as f32 * alpha as f32 / (255.0 * 255.0)).filter(|w| *w > 0.0)
+                }
+                None => mask.weight(empty.get_pixel(x, y)),
+            };
+            Luma([weight.unwrap_or(0.0)])
+        });
+
+        // Translucent mask pixels are only part of the mask with alpha intensity, they keep
+        // their smooth edges, everything else that isn't opaque is background
+        if !tint {
+            empty
+                .pixels_mut()
+                .zip(weights.pixels())
+                .filter(|(pixel, weight)| pixel.0[3] < 255 && weight.0[0] == 0.0)
+                .for_each(|(pixel, _)| *pixel = Rgba([0, 0, 0, 0]));
+        }
+
+        let progress = Progress::new(fill, &weights);
+        Self {
+            empty,
+            weights,
+            tint,
+            progress,
+        }
+    }
+
+    /// Scales the image to the size it's drawn at, returning how it looks empty on the
+    /// background and its mask pixels sorted by how far along the fill they are
+    fn scale(&self, bg: [u8; 4], width: u32, height: u32) -> (RgbaImage, Vec<MaskPixel>) {
+        let empty = compose(&self.empty, bg);
+        // What's left of each pixel with the fill taken out and what the fill covers, drawing
+        // puts the fill on top of those, so edges mix with their neighbours just like when
+        // scaling the finished image
+        let bg = premultiply(bg);
+        let split = |part: fn(f32) -> f32| {
+            Premultiplied::from_fn(empty.width(), empty.height(), |x, y| {
+                let weight = self.weights.get_pixel(x, y).0[0];
+                let under = match (self.tint, weight > 0.0) {
+                    (false, true) => bg,
+                    _ => empty.get_pixel(x, y).0,
+                };
+                Rgba(under.map(|channel| channel * part(weight)))
+            })
+        };
+        let mut rest = split(|weight| 1.0 - weight);
+        let mut covered = split(|weight| weight);
+        let mut weights = self.weights.clone();
+        if empty.dimensions() != (width, height) {
+            rest = imageops::resize(&rest, width, height, imageops::FilterType::Triangle);
+            covered = imageops::resize(&covered, width, height, imageops::FilterType::Triangle);
+            weights = imageops::resize(&weights, width, height, imageops::FilterType::Triangle);
+        }
+
+        let (scale_x, scale_y) = (
+            self.empty.width() as f32 / width as f32,
+            self.empty.height() as f32 / height as f32,
+        );
+        let mut pixels = weights
+            .enumerate_pixels()
+            .filter(|(_, _, weight)| weight.0[0] >= 1.0 / 255.0)
+            .map(|(x, y, weight)| MaskPixel {
+                x,
+                y,
+                // Pixel centres, so a full battery fills every pixel and an empty one none
+                progress: self
+                    .progress
+                    .at((x as f32 + 0.5) * scale_x, (y as f32 + 0.5) * scale_y),
+                weight: weight.0[0].min(1.0),
+                rest: quantize(rest.get_pixel(x, y).0),
+                covered: quantize(covered.get_pixel(x, y).0),
+            })
+            .collect::<Vec<_>>();
+        pixels.sort_by(|a, b| a.progress.total_cmp(&b.progress));
+
+        (finish(&empty, width, height), pixels)
+    }
+}
+
+/// Frames by the capacity they were drawn for, sorted by it
+struct Frames {
+    on_battery: Vec<(u8, DynamicImage)>,
+    /// Used while charging, falls back to the other frames when there are none
+    charging: Vec<(u8, DynamicImage)>,
+}
+
+impl Frames {
+    /// Loads the frames in `dir`, either as <level>.png files or as a sheet.png with the frames
+    /// side by side, spread evenly from 0 to 100%
+    fn load(dir: &Path, count: Option<u32>) -> Result<Vec<(u8, DynamicImage)>, Box<dyn Error>> {
+        let sheet_path = dir.join("sheet.png");
+        if sheet_path.exists() {
+            let sheet = image::open(&sheet_path)?;
+            // Without a frame count the frames are taken to be square
+            let count = count
+                .unwrap_or(sheet.width() / sheet.height().max(1))
+                .clamp(1, sheet.width().max(1));
+            let width = sheet.width() / count;
+            return Ok((0..count)
+                .map(|i| {
+                    let level = match count {
+                        1 => 100,
+                        _ => (i as f32 * 100.0 / (count - 1) as f32).round() as u8,
+                    };
+                    (level, sheet.crop_imm(i * width, 0, width, sheet.height()))
+                })
+                .collect());
+        }
+
+        let mut frames = fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "png" {
+                    return None;
+                }
+                let level = path.file_stem()?.to_str()?.parse::<u8>().ok()?;
+                (level <= 100).then_some((level, path))
+            })
+            .map(|(level, path)| Ok((level, image::open(path)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        frames.sort_by_key(|(level, _)| *level);
+
+        Ok(frames)
+    }
+
+    /// Picks the frame drawn for the level closest to the capacity, along with a number that
+    /// tells it apart from the other frames
+    fn get(&self, status: &BatteryStatus, capacity: f32) -> (usize, &DynamicImage) {
+        let (offset, frames) = match status {
+            BatteryStatus::Charging if !self.charging.is_empty() => {
+                (self.on_battery.len(), &self.charging)
+            }
+            _ => (0, &self.on_battery),
+        };
+        frames
+            .iter()
+            .enumerate()
+            .min_by(|(_, (a, _)), (_, (b, _))| {
+                (*a as f32 - capacity)
+                    .abs()
+                    .total_cmp(&(*b as f32 - capacity).abs())
+            })
+            .map(|(i, (_, frame))| (offset + i, frame))
+            .expect("Frames are never empty")
+    }
+}
+
+impl Indicator {
+    /// Loads images/<name>.png, or images/<name>/icon.png together with its mask.png, or the
+    /// frames in images/<name>/ and images/<name>/charging/
+    pub fn load(ruin_dir: &Path, name: &str, config: &Config) -> Result<Self, Box<dyn Error>> {
+        let image_config = config.images.get(name);
+        let (fill, mask) = image_config
+            .map(|image| (image.fill, image.mask))
+            .unwrap_or_default();
+        let theme_dir = ruin_dir.join("images").join(name);
+        let source = match (theme_dir.join("icon.png").exists(), theme_dir.is_dir()) {
+            (true, _) => {
+                let image = image::open(theme_dir.join("icon.png"))
+                    .map_err(|err| format!("Failed to load {name}/icon.png: {err}"))?;
+                let mask_image = image::open(theme_dir.join("mask.png"))
+                    .map_err(|err| format!("Failed to load {name}/mask.png: {err}"))?
+                    .to_luma_alpha8();
+                let mask_image = match mask_image.dimensions() == image.dimensions() {
+                    true => mask_image,
+                    false => imageops::resize(
+                        &mask_image,
+                        image.width(),
+                        image.height(),
+                        imageops::FilterType::Triangle,
+                    ),
+                };
+                Source::Mask(Classified::new(image, Some(mask_image), fill, mask))
+            }
+            (false, true) => {
+                let count = image_config.and_then(|image| image.frames);
+                let on_battery = Frames::load(&theme_dir, count)
+                    .map_err(|err| format!("Failed to load the frames of {name}: {err}"))?;
+                if on_battery.is_empty() {
+                    return Err(format!("No frames found in images/{name}, name them after their level (e.g. 50.png) or put them in a sheet.png").into());
+                }
+                let charging_dir = theme_dir.join("charging");
+                let charging = match charging_dir.is_dir() {
+                    true => Frames::load(&charging_dir, count).map_err(|err| {
+                        format!("Failed to load the charging frames of {name}: {err}")
+                    })?,
+                    false => Vec::new(),
+                };
+                Source::Frames(Frames {
+                    on_battery,
+                    charging,
+                })
+            }
+            (false, false) => {
+                let img_path = ruin_dir.join(format!("images/{}.png", name));
+                let image = image::open(img_path)
+                    .map_err(|err| format!("Failed to load {name}.png: {err}"))?;
+                Source::Mask(Classified::new(image, None, fill, mask))
+            }
+        };
+        let label = config
+            .text
+            .clone()
+            .map(|text| Label::load(text).map_err(|err| format!("Failed to load text: {err}")))
+            .transpose()?;
+
+        Ok(Self { source, label })
+    }
+
+    /// Size of the image, frames are all drawn at the size of the first one
+    fn dimensions(&self) -> (u32, u32) {
+        match &self.source {
+            Source::Mask(classified) => classified.empty.dimensions(),
+            Source::Frames(frames) => frames.on_battery[0].1.dimensions(),
+        }
+    }
+}
+
+/// A filled pixel of the wallpaper, with how strongly and how far along the filled part it is
+pub type Filled = (u32, u32, f32, f32);
+
+/// A pixel of the mask where it ends up on the wallpaper
+struct MaskPixel {
+    x: u32,
+    y: u32,
+    progress: f32,
+    weight: f32,
+    /// What's left of the pixel with the fill taken out, premultiplied
+    rest: [u8; 4],
+    /// What the fill covers, premultiplied, it shows through fills that aren't opaque
+    covered: [u8; 4],
+}
+
+/// What a pack was last drawn with, the fill only has to be redrawn between the old and new
+/// level as long as the colors stay the same
+#[derive(Clone, Copy, PartialEq)]
+struct PackState {
+    level: f32,
+    health: f32,
+    color: [u8; 4],
+    lost_color: [u8; 4],
+}
+
+/// One indicator on the wallpaper
+struct Pack {
+    x: i64,
+    y: i64,
+    width: u32,
+    height: u32,
+    /// Mask pixels sorted by how far along the fill they are, empty for frames
+    pixels: Vec<MaskPixel>,
+    state: Option<PackState>,
+    /// The shown frame, scaled and on the background
+    frame: Option<(usize, RgbaImage)>,
+    /// The shown text and where it is
+    label: Option<(String, i64, i64, RgbaImage)>,
+}
+
+/// A wallpaper kept around between redraws, the background and the empty indicators are only
+/// drawn once and a new battery level only redraws the pixels that change
+pub struct Canvas {
+    /// The wallpaper with every indicator empty and without text
+    base: RgbaImage,
+    image: RgbaImage,
+    packs: Vec<Pack>,
+    bg: [u8; 4],
+}
+
+impl Canvas {
+    pub fn new(
+        indicator: &Indicator,
+        color_scheme: &Colors,
+        count: usize,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let bg = color_scheme.background.0;
+        let mut base = RgbaImage::from_pixel(width, height, Rgba(bg));
+
+        // Images are made for a 3840x2160 screen, so they're scaled to take up the same part of
+        // every other screen
+        let scale = (width as f32 / 3840.0).min(height as f32 / 2160.0);
+        let (image_width, image_height) = indicator.dimensions();
+        let icon_width = ((image_width as f32 * scale) as u32).max(1);
+        let icon_height = ((image_height as f32 * scale) as u32).max(1);
+        let icon = match &indicator.source {
+            Source::Mask(classified) => Some(classified.scale(bg, icon_width, icon_height)),
+            Source::Frames(_) => None,
+        };
+
+        // Packs are laid out side by side with a quarter of the icon width between them
+        let gap = icon_width as i64 / 4;
+        let total = count as i64 * icon_width as i64 + (count as i64 - 1).max(0) * gap;
+        let y = (height as i64 - icon_height as i64) / 2;
+        let packs = (0..count as i64)
+            .map(|i| {
+                let x = (width as i64 - total) / 2 + i * (icon_width as i64 + gap);
+                let pixels = match &icon {
+                    Some((empty, pixels)) => {
+                        imageops::replace(&mut base, empty, x, y);
+                        pixels
+                            .iter()
+                            .filter_map(|pixel| {
+                                let px = u32::try_from(x + pixel.x as i64).ok()?;
+                                let py = u32::try_from(y + pixel.y as i64).ok()?;
+                                (px < width && py < height).then_some(MaskPixel {
+                                    x: px,
+                                    y: py,
+                                    ..*pixel
+                                })
+                            })
+                            .collect()
+                    }
+                    None => Vec::new(),
+                };
+                Pack {
+                    x,
+                    y,
+                    width: icon_width,
+                    height: icon_height,
+                    pixels,
+                    state: None,
+                    frame: None,
+                    label: None,
+                }
+            })
+            .collect();
+
+        Self {
+            image: base.clone(),
+            base,
+            packs,
+            bg,
+        }
+    }
+
+    /// Whether the canvas can be reused for this size and number of packs
+    pub fn fits(&self, width: u32, height: u32, count: usize) -> bool {
+        self.base.dimensions() == (width, height) && self.packs.len() == count
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    pub fn filled(&self) -> Vec<Filled> {
+        self.packs
+            .iter()
+            .filter_map(|pack| Some((pack, pack.state?)))
+            .flat_map(|(pack, state)| {
+                let end = pack
+                    .pixels
+                    .partition_point(|pixel| pixel.progress < state.level);
+                pack.pixels[..end].iter().map(move |pixel| {
+                    (pixel.x, pixel.y, pixel.weight, pixel.progress / state.level)
+                })
+            })
+            .collect()
+    }
+
+    /// Draws the batteries, `batteries` has one entry per pack
+    pub fn update(
+        &mut self,
+        batteries: &[Battery],
+        color_scheme: &Colors,
+        config: &Config,
+        indicator: &Indicator,
+    ) {
+        let Self {
+            base,
+            image,
+            packs,
+            bg,
+        } = self;
+
+        let states = packs
+            .iter()
+            .zip(batteries)
+            .map(|(pack, battery)| {
+                let state = PackState::new(battery, color_scheme, config);
+                let range = pack.changed(&state);
+                (state, range)
+            })
+            .collect::<Vec<_>>();
+        // What gets painted over, text on top of it has to be drawn again
+        let repainted = packs
+            .iter()
+            .zip(batteries)
+            .zip(&states)
+            .filter_map(|((pack, battery), (_, range))| match &indicator.source {
+                Source::Mask(_) => bounds(&pack.pixels[range.clone()]),
+                Source::Frames(frames) => {
+                    let (key, _) = frames.get(&battery.status, battery.capacity);
+                    let shown = pack.frame.as_ref().map(|(shown, _)| *shown);
+                    (shown != Some(key)).then(|| pack.rect())
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Text is wiped first as it may be on top of an indicator, which is then redrawn below
+        let texts = batteries
+            .iter()
+            .map(|battery| {
+                let label = indicator.label.as_ref()?;
+                Some(text::format(&label.text.format, battery))
+            })
+            .collect::<Vec<_>>();
+        let relabel = packs.iter().zip(&texts).any(|(pack, text)| {
+            pack.label.as_ref().map(|label| &label.0) != text.as_ref()
+                || pack
+                    .label_rect()
+                    .is_some_and(|label| repainted.iter().any(|rect| overlaps(*rect, label)))
+        });
+        let wiped = match relabel {
+            true => packs
+                .iter_mut()
+                .filter_map(|pack| {
+                    let rect = pack.label_rect()?;
+                    pack.label = None;
+                    restore(image, base, rect);
+                    Some(rect)
+                })
+                .collect(),
+            false => Vec::new(),
+        };
+
+        packs.iter_mut().zip(batteries).zip(states).for_each(
+            |((pack, battery), (state, range))| {
+                let hit = wiped.iter().any(|rect| overlaps(*rect, pack.rect()));
+                match &indicator.source {
+                    Source::Mask(_) => {
+                        pack.pixels[range].iter().for_each(|pixel| {
+                            image.put_pixel(pixel.x, pixel.y, state.paint(pixel, base))
+                        });
+                        if hit {
+                            pack.pixels
+                                .iter()
+                                .filter(|pixel| wiped.iter().any(|rect| contains(*rect, pixel)))
+                                .for_each(|pixel| {
+                                    image.put_pixel(pixel.x, pixel.y, state.paint(pixel, base))
+                                });
+                        }
+                        pack.state = Some(state);
+                    }
+                    Source::Frames(frames) => {
+                        let (key, frame) = frames.get(&battery.status, battery.capacity);
+                        if pack.frame.as_ref().map(|(shown, _)| *shown) != Some(key) {
+                            // Frames are drawn as they are, only their transparent parts show
+                            // the background
+                            let scaled =
+                                finish(&compose(&frame.to_rgba8(), *bg), pack.width, pack.height);
+                            pack.frame = Some((key, scaled));
+                        } else if !hit {
+                            return;
+                        }
+                        if let Some((_, frame)) = &pack.frame {
+                            imageops::replace(image, frame, pack.x, pack.y);
+                        }
+                    }
+                }
+            },
+        );
+
+        if !relabel {
+            return;
+        }
+        let scale = (base.width() as f32 / 3840.0).min(base.height() as f32 / 2160.0);
+        packs
+            .iter_mut()
+            .zip(batteries)
+            .zip(texts)
+            .for_each(|((pack, battery), text)| {
+                let Some((label, text)) = indicator.label.as_ref().zip(text) else {
+                    return;
+                };
+                let Some(rendered) = label.render(battery, scale) else {
+                    return;
+                };
+                let (x, y) = pack.label_position(label.text.position, &rendered);
+                imageops::overlay(image, &rendered, x, y);
+                pack.label = Some((text, x, y, rendered));
+            });
+    }
+}
+
+impl Pack {
+    fn rect(&self) -> (i64, i64, u32, u32) {
+        (self.x, self.y, self.width, self.height)
+    }
+
+    fn label_rect(&self) -> Option<(i64, i64, u32, u32)> {
+        let (_, x, y, label) = self.label.as_ref()?;
+        Some((*x, *y, label.width(), label.height()))
+    }
+
+    /// Mask pixels that look different with `state` than with the shown one
+    fn changed(&self, state: &PackState) -> Range<usize> {
+        match self.state {
+            Some(old) if old.same_colors(state) => {
+                let (from, to) = match old.level < state.level {
+                    true => (old.level, state.level),
+                    false => (state.level, old.level),
+                };
+                self.pixels.partition_point(|pixel| pixel.progress < from)
+                    ..self.pixels.partition_point(|pixel| pixel.progress < to)
+            }
+            _ => 0..self.pixels.len(),
+        }
+    }
+
+    fn label_position(&self, position: Position, text: &RgbaImage) -> (i64, i64) {
+        let (x, y) = (self.x, self.y);
+        let (icon_width, icon_height) = (self.width as i64, self.height as i64);
+        let (text_width, text_height) = (text.width() as i64, text.height() as i64);
+        let margin = text_height / 2;
+        match position {
+            Position::Above => (x + (icon_width - text_width) / 2, y - margin - text_height),
+            Position::Below => (x + (icon_width - text_width) / 2, y + icon_height + margin),
+            Position::Left => (x - margin - text_width, y + (icon_height - text_height) / 2),
+            Position::Right => (x + icon_width + margin, y + (icon_height - text_height) / 2),
+            Position::Center => (
+                x + (icon_width - text_width) / 2,
+                y + (icon_height - text_height) / 2,
+            ),
+        }
+    }
+}
+
+impl PackState {
+    fn new(battery: &Battery, color_scheme: &Colors, config: &Config) -> Self {
+        let color = color_scheme.fill(battery, config.low_battery);
+        let lost_color = color_scheme.lost_capacity;
+
+        // Worn batteries are measured against their design capacity, so the part they can't
+        // hold anymore never fills up
+        let health = match (config.layout.health, battery.health) {
+            (true, Some(health)) => (health / 100.0).min(1.0),
+            _ => 1.0,
+        };
+
+        Self {
+            level: battery.capacity / 100.0 * health,
+            health,
+            color: color.0,
+            lost_color: lost_color.0,
+        }
+    }
+
+    fn same_colors(&self, other: &Self) -> bool {
+        (self.health, self.color, self.lost_color) == (other.health, other.color, other.lost_color)
+    }
+
+    fn paint(&self, pixel: &MaskPixel, base: &RgbaImage) -> Rgba<u8> {
+        match pixel.progress {
+            progress if progress < self.level => add(pixel, self.color),
+            progress if self.health < 1.0 && progress >= self.health => add(pixel, self.lost_color),
+            _ => *base.get_pixel(pixel.x, pixel.y),
+        }
+    }
+}
+
+/// Color channels multiplied by alpha, from 0 to 1, see-through pixels mix like opaque ones
+/// in this form without their color bleeding into their neighbours
+type Premultiplied = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+fn premultiply([r, g, b, a]: [u8; 4]) -> [f32; 4] {
+    let alpha = a as f32 / 255.0;
+    [
+        r as f32 / 255.0 * alpha,
+        g as f32 / 255.0 * alpha,
+        b as f32 / 255.0 * alpha,
+        alpha,
+    ]
+}
+
+fn unpremultiply([r, g, b, alpha]: [f32; 4]) -> Rgba<u8> {
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let [r, g, b] = [r, g, b].map(|channel| (channel / alpha * 255.0).round().min(255.0) as u8);
+    Rgba([r, g, b, (alpha * 255.0).round().min(255.0) as u8])
+}
+
+/// Stores a premultiplied pixel in a byte per channel
+fn quantize(pixel: [f32; 4]) -> [u8; 4] {
+    pixel.map(|channel| (channel * 255.0).round().min(255.0) as u8)
+}
+
+/// Puts the image on the background, before it's scaled so transparent edges don't darken
+fn compose(image: &RgbaImage, bg: [u8; 4]) -> Premultiplied {
+    let bg = premultiply(bg);
+    Premultiplied::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = premultiply(image.get_pixel(x, y).0);
+        let behind = 1.0 - pixel[3];
+        Rgba([0, 1, 2, 3].map(|i| pixel[i] + bg[i] * behind))
+    })
+}
+
+/// Scales a composed image to the size it's drawn at
+fn finish(image: &Premultiplied, width: u32, height: u32) -> RgbaImage {
+    let resized;
+    let image = match image.dimensions() == (width, height) {
+        true => image,
+        false => {
+            resized = imageops::resize(image, width, height, imageops::FilterType::Triangle);
+            &resized
+        }
+    };
+    RgbaImage::from_fn(width, height, |x, y| unpremultiply(image.get_pixel(x, y).0))
+}
+
+/// Puts the fill color on top of what's left of a pixel, what it covers shows through as far
+/// as the color is see-through
+fn add(pixel: &MaskPixel, color: [u8; 4]) -> Rgba<u8> {
+    let color = premultiply(color);
+    let behind = 1.0 - color[3];
+    unpremultiply([0, 1, 2, 3].map(|i| {
+        (pixel.rest[i] as f32 + pixel.covered[i] as f32 * behind) / 255.0 + color[i] * pixel.weight
+    }))
+}
+
+/// Copies a part of the base back onto the wallpaper
+fn restore(image: &mut RgbaImage, base: &RgbaImage, (x, y, width, height): (i64, i64, u32, u32)) {
+    let x0 = x.clamp(0, base.width() as i64) as u32;
+    let y0 = y.clamp(0, base.height() as i64) as u32;
+    let x1 = (x + width as i64).clamp(0, base.width() as i64) as u32;
+    let y1 = (y + height as i64).clamp(0, base.height() as i64) as u32;
+    (y0..y1)
+        .for_each(|py| (x0..x1).for_each(|px| image.put_pixel(px, py, *base.get_pixel(px, py))));
+}
+
+fn overlaps(a: (i64, i64, u32, u32), b: (i64, i64, u32, u32)) -> bool {
+    a.0 < b.0 + b.2 as i64
+        && b.0 < a.0 + a.2 as i64
+        && a.1 < b.1 + b.3 as i64
+        && b.1 < a.1 + a.3 as i64
+}
+
+/// The smallest rect around `pixels`
+fn bounds(pixels: &[MaskPixel]) -> Option<(i64, i64, u32, u32)> {
+    let first = pixels.first()?;
+    let (x0, y0, x1, y1) = pixels.iter().fold(
+        (first.x, first.y, first.x, first.y),
+        |(x0, y0, x1, y1), pixel| {
+            (
+                x0.min(pixel.x),
+                y0.min(pixel.y),
+                x1.max(pixel.x),
+                y1.max(pixel.y),
+            )
+        },
+    );
+    Some((x0 as i64, y0 as i64, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+fn contains((x, y, width, height): (i64, i64, u32, u32), pixel: &MaskPixel) -> bool {
+    let (px, py) = (pixel.x as i64, pixel.y as i64);
+    px >= x && px < x + width as i64 && py >= y && py < y + height as i64
+}
+
+/// Renders the wallpaper from scratch
+pub fn create(
+    batteries: &[Battery],
+    color_scheme: &Colors,
+    config: &Config,
+    indicator: &Indicator,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut canvas = Canvas::new(indicator, color_scheme, batteries.len(), width, height);
+    canvas.update(batteries, color_scheme, config, indicator);
+    canvas.image
+}
+
+pub fn blend(from: [u8; 4], to: [u8; 4], weight: f32) -> Rgba<u8> {
+    let mut blended = from;
+    blended.iter_mut().zip(to).for_each(|(from, to)| {
+        *from = (*from as f32 + (to as f32 - *from as f32) * weight).round() as u8
+    });
+    Rgba(blended)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{ImageConfig, Layout},
+        text::Text,
+    };
+    use std::{collections::HashMap, env, path::PathBuf, process};
+
+    /// A config directory with a battery shaped image and a theme made of frames
+    struct RuinDir(PathBuf);
+
+    impl RuinDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("ruin-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("images/frames/charging")).unwrap();
+
+            let battery = RgbaImage::from_fn(120, 240, |x, y| match (x, y) {
+                (45..=74, 0..=19) | (10..=109, 20..=29) | (10..=109, 230..=239) => {
+                    Rgba([40, 40, 40, 255])
+                }
+                (10..=19 | 100..=109, 30..=229) => Rgba([40, 40, 40, 255]),
+                (20..=99, 30..=229) => Rgba([0x8f, 0xbc, 0xbb, 255]),
+                _ => Rgba([0, 0, 0, 0]),
+            });
+            battery.save(dir.join("images/battery.png")).unwrap();
+            for (frame, color) in [
+                ("0.png", [191, 19, 28, 255]),
+                ("50.png", [91, 194, 54, 128]),
+                ("100.png", [91, 194, 54, 255]),
+                ("charging/50.png", [255, 255, 0, 200]),
+            ] {
+                RgbaImage::from_fn(60, 60, |x, y| match (x + y) % 7 {
+                    0 => Rgba([0, 0, 0, 0]),
+                    _ => Rgba(color),
+                })
+                .save(dir.join("images/frames").join(frame))
+                .unwrap();
+            }
+
+            Self(dir)
+        }
+    }
+
+    impl Drop for RuinDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn battery(status: BatteryStatus, capacity: f32, health: Option<f32>) -> Battery {
+        Battery {
+            status,
+            capacity,
+            energy: None,
+            rate: None,
+            time_to_empty: None,
+            time_to_full: None,
+            health,
+            cycle_count: None,
+        }
+    }
+
+    /// Draws the levels one after the other on the same canvas and compares every one with a
+    /// wallpaper drawn from scratch
+    fn redraw(ruin_dir: &RuinDir, name: &str, config: &Config, health: Option<f32>) {
+        use BatteryStatus::*;
+        let indicator = Indicator::load(&ruin_dir.0, name, config).unwrap();
+        let colors = Colors::default();
+        let levels = [
+            (Discharging, 50.4),
+            (Discharging, 49.6),
+            (Discharging, 49.4),
+            (Discharging, 31.0),
+            (Discharging, 29.0),
+            (Discharging, 12.3),
+            (Discharging, 0.0),
+            (Charging, 0.4),
+            (Charging, 55.5),
+            (Charging, 99.6),
+            (Full, 100.0),
+            (NotCharging, 80.0),
+            (Discharging, 79.5),
+        ];
+
+        for (width, height) in [(320, 180), (480, 480), (640, 360)] {
+            let mut canvas = Canvas::new(&indicator, &colors, 2, width, height);
+            for (status, level) in &levels {
+                let batteries = [
+                    battery(status.clone(), *level, health),
+                    battery(status.clone(), 100.0 - level, health),
+                ];
+                canvas.update(&batteries, &colors, config, &indicator);
+                let fresh = create(&batteries, &colors, config, &indicator, width, height);
+                assert!(
+                    canvas.image() == &fresh,
+                    "{name} at {width}x{height} differs after redrawing {status:?} at {level}%",
+                );
+            }
+        }
+    }
+
+    /// A label over the indicator, None without a system font to draw it with
+    fn text() -> Option<Text> {
+        Label::load(Text::default()).ok().map(|_| Text {
+            position: Position::Center,
+            ..Text::default()
+        })
+    }
+
+    fn config(direction: Direction, health: bool, text: Option<Text>) -> Config {
+        let fill = Fill {
+            direction,
+            start: 90.0,
+        };
+        Config {
+            layout: Layout {
+                health,
+                ..Layout::default()
+            },
+            text,
+            images: HashMap::from([(
+                "battery".to_string(),
+                ImageConfig {
+                    fill,
+                    ..ImageConfig::default()
+                },
+            )]),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn redraws_match_drawing_from_scratch() {
+        let ruin_dir = RuinDir::new("redraw");
+        // Without a system font only the indicators are compared
+        let text = text();
+
+        for direction in [
+            Direction::BottomUp,
+            Direction::LeftToRight,
+            Direction::Radial,
+            Direction::Arc,
+        ] {
+            for health in [false, true] {
+                let config = config(direction, health, text.clone());
+                redraw(&ruin_dir, "battery", &config, health.then_some(70.0));
+            }
+        }
+        let config = Config {
+            text,
+            ..Config::default()
+        };
+        redraw(&ruin_dir, "frames", &config, None);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{
+    battery::{format_duration, Battery},
+    color::Color,
+};
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use std::{error::Error, fs, path::PathBuf, time::Duration};
+
+/// Fonts tried when none is configured, where common distributions put them
+const FONTS: &[&str] = &[
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+];
+
+/// Text drawn next to every indicator
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Text {
+    /// Template of the text, {capacity}, {status}, {time_to_empty}, {time_to_full} and
+    /// {health} are replaced with the state of the battery
+    pub format: String,
+    /// TTF or OTF font, a system font is looked for if not given
+    pub font: Option<PathBuf>,
+    /// Height of the text in pixels on a 3840x2160 screen
+    pub size: f32,
+    pub color: Color,
+    pub position: Position,
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Self {
+            format: "{capacity}%".to_string(),
+            font: None,
+            size: 120.0,
+            color: Color::rgb(255, 255, 255),
+            position: Position::default(),
+        }
+    }
+}
+
+/// Where the text goes relative to the indicator
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    Above,
+    #[default]
+    Below,
+    Left,
+    Right,
+    /// On top of the indicator
+    Center,
+}
+
+/// Text settings together with the loaded font
+pub struct Label {
+    font: FontVec,
+    pub text: Text,
+}
+
+impl Label {
+    pub fn load(text: Text) -> Result<Self, Box<dyn Error>> {
+        let path = match &text.font {
+            Some(path) => path.clone(),
+            None => FONTS
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists())
+                .ok_or("No font found, set text.font in config.yaml")?,
+        };
+        let file = fs::read(&path)
+            .map_err(|err| format!("Failed to read font {}: {err}", path.display()))?;
+        let font = FontVec::try_from_vec(file)
+            .map_err(|err| format!("Invalid font {}: {err}", path.display()))?;
+
+        Ok(Self { font, text })
+    }
+
+    /// Draws the text for the battery on a transparent image just big enough to hold it, None
+    /// when there's nothing to draw
+    pub fn render(&self, battery: &Battery, scale: f32) -> Option<RgbaImage> {
+        let text = format(&self.text.format, battery);
+        if text.is_empty() {
+            return None;
+        }
+
+        let font = self.font.as_scaled(PxScale::from(self.text.size * scale));
+        let mut glyphs = Vec::new();
+        let mut caret = 0.0;
+        let mut previous = None;
+        text.chars().for_each(|c| {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            glyphs.push(id.with_scale_and_position(font.scale(), (caret, font.ascent())));
+            caret += font.h_advance(id);
+            previous = Some(id);
+        });
+
+        let width = caret.ceil() as u32;
+        let height = font.height().ceil() as u32;
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let [r, g, b, a] = self.text.color.0;
+        let mut image = RgbaImage::new(width, height);
+        glyphs
+            .into_iter()
+            .filter_map(|glyph| font.outline_glyph(glyph))
+            .for_each(|glyph| {
+                let bounds = glyph.px_bounds();
+                glyph.draw(|x, y, coverage| {
+                    let x = bounds.min.x as i32 + x as i32;
+                    let y = bounds.min.y as i32 + y as i32;
+                    if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                        return;
+                    }
+                    // Glyphs may overlap a little, keep the strongest coverage
+                    let pixel = image.get_pixel_mut(x as u32, y as u32);
+                    let alpha = (coverage.min(1.0) * a as f32).round() as u8;
+                    if alpha > pixel.0[3] {
+                        *pixel = Rgba([r, g, b, alpha]);
+                    }
+                });
+            });
+
+        Some(image)
+    }
+}
+
+/// Fills the placeholders of the template in with the state of the battery
+pub fn format(template: &str, battery: &Battery) -> String {
+    let time = |time: Option<Duration>| time.map(format_duration).unwrap_or_default();
+    template
+        .replace("{capacity}", &format!("{:.0}", battery.capacity))
+        .replace("{status}", &battery.status.to_string())
+        .replace("{time_to_empty}", &time(battery.time_to_empty))
+        .replace("{time_to_full}", &time(battery.time_to_full))
+        .replace(
+            "{health}",
+            &battery
+                .health
+                .map(|health| format!("{health:.0}"))
+                .unwrap_or_default(),
+        )
+        .trim()
+        .to_string()
+}